@@ -0,0 +1,400 @@
+//! pushes alerts to the v2 api of a prometheus alertmanager
+//! https://prometheus.io/docs/alerting/latest/clients/
+use crate::alerts::Alert;
+use crate::collector::{CollectorHandle, Snapshot};
+use crate::meshviewer::format_time;
+use crate::metrics::{label_value, NODE_LABELS};
+use crate::Endpoint;
+use crate::{NodeId, NodeResponse, Timestamp, CONFIG};
+use chrono::Utc;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+use serde_json as json;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const API_PATH: &str = "/api/v2/alerts";
+const TICK: Duration = Duration::from_secs(1);
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+/// alertname of the built in offline alert
+const OFFLINE_ALERT: &str = "NodeOffline";
+/// Firing alerts expire after this many resend intervals, so they resolve
+/// on their own if requestd stops.
+const EXPIRE_AFTER_RESENDS: u32 = 3;
+
+
+/// an alert as accepted by `POST /api/v2/alerts`
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostableAlert {
+	pub labels: BTreeMap<String, String>,
+	pub annotations: BTreeMap<String, String>,
+	pub starts_at: Timestamp,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ends_at: Option<Timestamp>,
+}
+
+impl PostableAlert {
+	fn new(alertname: &str, nodeid: &str, response: Option<&NodeResponse>, starts_at: Timestamp) -> Self {
+		let mut labels = BTreeMap::new();
+		labels.insert("alertname".to_string(), alertname.to_string());
+		labels.insert("nodeid".to_string(), nodeid.to_string());
+
+		if let Some(response) = response {
+			for (name, pointer) in NODE_LABELS {
+				let value = label_value(&response.data, pointer);
+				if !value.is_empty() {
+					labels.insert(name.to_string(), value);
+				}
+			}
+		}
+
+		Self {
+			labels,
+			annotations: BTreeMap::new(),
+			starts_at,
+			ends_at: None,
+		}
+	}
+
+	fn from_alert(alert: &Alert, response: Option<&NodeResponse>) -> Self {
+		let mut postable = Self::new(&alert.rule, &alert.nodeid, response, alert.since);
+		if let Some(hostname) = &alert.hostname {
+			postable.labels.entry("hostname".to_string()).or_insert_with(|| hostname.clone());
+		}
+
+		let op = json::to_value(alert.op).unwrap();
+		postable.annotations.insert(
+			"summary".to_string(),
+			format!("{} on {}", alert.rule, alert.hostname.as_deref().unwrap_or(&alert.nodeid)),
+		);
+		postable.annotations.insert(
+			"description".to_string(),
+			format!("{} {} {}", alert.value, op.as_str().unwrap_or_default(), alert.threshold),
+		);
+
+		postable
+	}
+
+	fn offline(response: &NodeResponse) -> Self {
+		let mut postable = Self::new(OFFLINE_ALERT, &response.nodeid, Some(response), response.timestamp);
+		let name = postable.labels.get("hostname").cloned().unwrap_or_else(|| response.nodeid.clone());
+
+		postable.annotations.insert("summary".to_string(), format!("{} is offline", name));
+		postable.annotations.insert(
+			"description".to_string(),
+			format!("last response at {}", format_time(response.timestamp)),
+		);
+
+		postable
+	}
+}
+
+
+/// the http side, separate so it can be tested without a collector
+pub struct Client {
+	agent: ureq::Agent,
+	url: String,
+}
+
+impl Client {
+	pub fn new(base_url: &str) -> Self {
+		Self {
+			agent: ureq::AgentBuilder::new().timeout(PUSH_TIMEOUT).build(),
+			url: format!("{}{}", base_url.trim_end_matches('/'), API_PATH),
+		}
+	}
+
+	pub fn push(&self, alerts: &[PostableAlert]) -> Result<(), String> {
+		let body = json::to_vec(alerts).unwrap();
+
+		self.agent
+			.post(&self.url)
+			.set("Content-Type", "application/json")
+			.send_bytes(&body)
+			.map(|_| ())
+			.map_err(|e| e.to_string())
+	}
+}
+
+
+/// Offline alerts of all nodes ever seen. The alert of a node that was
+/// removed from the buffer keeps firing until it responds again.
+struct OfflineTracker {
+	/// seconds
+	offline_after: u64,
+	/// seconds
+	forget_after: u64,
+	/// the offline alert of every node as of its last response
+	last: HashMap<NodeId, PostableAlert>,
+}
+
+impl OfflineTracker {
+	fn new(offline_after: u64, forget_after: u64) -> Self {
+		Self {
+			offline_after,
+			forget_after,
+			last: HashMap::new(),
+		}
+	}
+
+	/// All nodes that didn't respond for `offline_after`. Nodes that didn't
+	/// respond for `forget_after` are forgotten, so their alerts resolve.
+	fn check(&mut self, responses: &[Arc<NodeResponse>], now: Timestamp) -> HashMap<NodeId, PostableAlert> {
+		for response in responses {
+			let outdated = self.last.get(&response.nodeid).is_none_or(|a| a.starts_at < response.timestamp);
+			if outdated {
+				self.last.insert(response.nodeid.clone(), PostableAlert::offline(response));
+			}
+		}
+
+		let forget_after = self.forget_after as i64;
+		self.last.retain(|_, alert| (now - alert.starts_at).num_seconds() <= forget_after);
+
+		let offline_after = self.offline_after as i64;
+		self.last
+			.iter()
+			.filter(|(_, alert)| (now - alert.starts_at).num_seconds() > offline_after)
+			.map(|(nodeid, alert)| (nodeid.clone(), alert.clone()))
+			.collect()
+	}
+}
+
+
+/// What the alertmanager should know about. Always compared with the full
+/// set of active alerts, so nothing is missed if requestd was busy.
+#[derive(Default)]
+struct ActiveAlerts {
+	/// (alertname, node id) -> alert
+	firing: HashMap<(String, NodeId), PostableAlert>,
+	/// resolved alerts that weren't pushed yet
+	resolved: Vec<PostableAlert>,
+}
+
+impl ActiveAlerts {
+	/// Fire the new alerts of `active` and resolve the ones that `owns` and
+	/// are missing in `active`. Returns true if something changed.
+	fn sync<I, F>(&mut self, active: I, owns: F, now: Timestamp) -> bool
+	where
+		I: IntoIterator<Item = ((String, NodeId), PostableAlert)>,
+		F: Fn(&str) -> bool,
+	{
+		let mut changed = false;
+		let mut keys = HashSet::new();
+		for (key, alert) in active {
+			keys.insert(key.clone());
+			if let Entry::Vacant(entry) = self.firing.entry(key) {
+				debug!("{} is firing for {}", entry.key().0, entry.key().1);
+				entry.insert(alert);
+				changed = true;
+			}
+		}
+
+		let gone: Vec<(String, NodeId)> =
+			self.firing.keys().filter(|key| owns(&key.0) && !keys.contains(*key)).cloned().collect();
+		for key in gone {
+			if let Some(mut postable) = self.firing.remove(&key) {
+				postable.ends_at = Some(now);
+				self.resolved.push(postable);
+				changed = true;
+			}
+		}
+
+		changed
+	}
+}
+
+
+pub struct Alertmanager {
+	collector: CollectorHandle,
+	client: Client,
+	resend_interval: Duration,
+	/// `None` if offline alerts are disabled
+	offline: Option<OfflineTracker>,
+	alerts: ActiveAlerts,
+	last_push: Instant,
+}
+
+impl Alertmanager {
+	/// the alerts of the configured rules, as firing in the snapshot
+	fn check_rules(&mut self, snapshot: &Snapshot, now: Timestamp) -> bool {
+		let active = snapshot.alerts().iter().map(|alert| {
+			let key = (alert.rule.clone(), alert.nodeid.clone());
+			(key, PostableAlert::from_alert(alert, snapshot.response(&alert.nodeid)))
+		});
+
+		self.alerts.sync(active, |name| name != OFFLINE_ALERT, now)
+	}
+
+	/// fire and resolve the built in offline alerts
+	fn check_offline(&mut self, snapshot: &Snapshot, now: Timestamp) -> bool {
+		let offline = match &mut self.offline {
+			Some(tracker) => tracker.check(snapshot.responses(), now),
+			None => return false,
+		};
+		let active = offline.into_iter().map(|(nodeid, alert)| ((OFFLINE_ALERT.to_string(), nodeid), alert));
+
+		self.alerts.sync(active, |name| name == OFFLINE_ALERT, now)
+	}
+
+	fn push(&mut self) {
+		self.last_push = Instant::now();
+
+		let expires = Utc::now() + chrono::Duration::from_std(self.resend_interval * EXPIRE_AFTER_RESENDS).unwrap();
+		let alerts: Vec<PostableAlert> = self
+			.alerts
+			.firing
+			.values()
+			.cloned()
+			.map(|mut a| {
+				a.ends_at = Some(expires);
+				a
+			})
+			.chain(self.alerts.resolved.iter().cloned())
+			.collect();
+
+		if alerts.is_empty() {
+			return;
+		}
+
+		trace!("pushing {} alerts to alertmanager", alerts.len());
+		match self.client.push(&alerts) {
+			Ok(()) => self.alerts.resolved.clear(),
+			// resolved alerts are sent again with the next push
+			Err(e) => error!("cannot push alerts to alertmanager: {}", e),
+		}
+	}
+}
+
+
+impl Endpoint for Alertmanager {
+	fn new(c: CollectorHandle) -> Self {
+		let conf = CONFIG.alertmanager.clone().unwrap();
+
+		Self {
+			collector: c,
+			client: Client::new(&conf.url),
+			resend_interval: Duration::from_secs(conf.resend_interval),
+			offline: conf.offline.then(|| OfflineTracker::new(CONFIG.requestd.offline_after, conf.forget_after)),
+			alerts: ActiveAlerts::default(),
+			last_push: Instant::now(),
+		}
+	}
+
+	fn start(mut self) -> ! {
+		loop {
+			let snapshot = self.collector.snapshot();
+			let now = Utc::now();
+
+			let mut changed = self.check_rules(&snapshot, now);
+			changed |= self.check_offline(&snapshot, now);
+
+			if changed || self.last_push.elapsed() >= self.resend_interval {
+				self.push();
+			}
+
+			thread::sleep(TICK);
+		}
+	}
+}
+
+
+
+#[test]
+fn push_to_alertmanager() {
+	let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+	let client = Client::new(&format!("http://{}/", server.server_addr()));
+
+	let receiver = std::thread::spawn(move || {
+		let mut req = server.recv().unwrap();
+		let mut body = String::new();
+		req.as_reader().read_to_string(&mut body).unwrap();
+		let url = req.url().to_string();
+		let method = req.method().to_string();
+		req.respond(tiny_http::Response::empty(200)).unwrap();
+		(method, url, body)
+	});
+
+	let response = NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: Utc::now(),
+		data: json::json!({"nodeinfo": {"hostname": "ffhl-test", "system": {"site_code": "ffhl"}}}),
+	};
+	client.push(&[PostableAlert::offline(&response)]).unwrap();
+
+	let (method, url, body) = receiver.join().unwrap();
+	assert_eq!(method, "POST");
+	assert_eq!(url, API_PATH);
+
+	let alerts: json::Value = json::from_str(&body).unwrap();
+	assert_eq!(alerts[0]["labels"]["alertname"], OFFLINE_ALERT);
+	assert_eq!(alerts[0]["labels"]["hostname"], "ffhl-test");
+	assert_eq!(alerts[0]["labels"]["site"], "ffhl");
+	assert!(alerts[0]["startsAt"].is_string());
+	assert!(alerts[0].get("endsAt").is_none());
+}
+
+#[test]
+fn offline_after_removal_from_buffer() {
+	let start = Utc::now();
+	let later = |seconds: i64| start + chrono::Duration::seconds(seconds);
+	let response = |timestamp: Timestamp| NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp,
+		data: json::json!({"nodeinfo": {"hostname": "ffhl-test"}}),
+	};
+
+	let mut tracker = OfflineTracker::new(180, 3600);
+	assert!(tracker.check(&[Arc::new(response(start))], later(10)).is_empty());
+
+	// the buffer dropped the node long before it counts as offline
+	assert!(tracker.check(&[], later(100)).is_empty());
+	let offline = tracker.check(&[], later(200));
+	assert_eq!(offline["c04a00dd692a"].labels["hostname"], "ffhl-test");
+	assert_eq!(offline["c04a00dd692a"].starts_at, start);
+	assert_eq!(tracker.check(&[], later(3600)).len(), 1);
+
+	assert!(tracker.check(&[Arc::new(response(later(3600)))], later(3601)).is_empty());
+
+	// gone for good
+	assert!(tracker.check(&[], later(7201)).is_empty());
+	assert!(tracker.last.is_empty());
+}
+
+#[test]
+fn active_alerts_follow_the_full_set() {
+	let now = Utc::now();
+	let response = NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: now,
+		data: json::json!({}),
+	};
+	let alert = |name: &str| {
+		let key = (name.to_string(), response.nodeid.clone());
+		(key, PostableAlert::new(name, &response.nodeid, None, now))
+	};
+	let is_rule = |name: &str| name != OFFLINE_ALERT;
+
+	let mut alerts = ActiveAlerts::default();
+	assert!(alerts.sync(vec![alert("high_load")], is_rule, now));
+	assert!(!alerts.sync(vec![alert("high_load")], is_rule, now));
+
+	// offline alerts don't resolve rules and the other way around
+	assert!(alerts.sync(vec![alert(OFFLINE_ALERT)], |name| name == OFFLINE_ALERT, now));
+	assert!(!alerts.sync(vec![alert("high_load")], is_rule, now));
+	assert_eq!(alerts.firing.len(), 2);
+
+	// resolved even if the event got lost
+	assert!(alerts.sync(vec![], is_rule, now));
+	assert_eq!(alerts.firing.len(), 1);
+	assert_eq!(alerts.resolved.len(), 1);
+	assert_eq!(alerts.resolved[0].labels["alertname"], "high_load");
+	assert_eq!(alerts.resolved[0].ends_at, Some(now));
+}
@@ -0,0 +1,447 @@
+//! threshold based alerts on the values of the responses
+use crate::config::{AlertMetric, AlertOp, AlertRule, Alerts};
+use crate::jsonpath;
+use crate::legacy::memory_usage;
+use crate::{NodeId, NodeResponse, Timestamp};
+use crossbeam::channel::{self, Sender, TrySendError};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+use serde_json as json;
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// alerts waiting to be sent to the webhook
+const WEBHOOK_QUEUE: usize = 256;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+	Firing,
+	Resolved,
+}
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Alert {
+	pub rule: String,
+	pub nodeid: NodeId,
+	pub hostname: Option<String>,
+	pub state: AlertState,
+	/// the value that caused the last change
+	pub value: json::Value,
+	pub op: AlertOp,
+	pub threshold: json::Value,
+	/// the condition holds since
+	pub since: Timestamp,
+	pub timestamp: Timestamp,
+}
+
+
+impl AlertOp {
+	fn check(&self, value: &json::Value, threshold: &json::Value) -> bool {
+		match (self, value.as_f64(), threshold.as_f64()) {
+			(Self::Greater, Some(v), Some(t)) => v > t,
+			(Self::GreaterOrEqual, Some(v), Some(t)) => v >= t,
+			(Self::Less, Some(v), Some(t)) => v < t,
+			(Self::LessOrEqual, Some(v), Some(t)) => v <= t,
+			(Self::Equal, Some(v), Some(t)) => v == t,
+			(Self::NotEqual, Some(v), Some(t)) => v != t,
+			(Self::Equal, _, _) => value == threshold,
+			(Self::NotEqual, _, _) => value != threshold,
+			_ => false,
+		}
+	}
+}
+
+
+impl AlertRule {
+	fn applies_to(&self, nodeid: &str) -> bool {
+		self.nodes.is_empty() || self.nodes.iter().any(|n| n == nodeid)
+	}
+
+	/// `None` if the response doesn't contain the value
+	fn value(&self, response: &NodeResponse, now: Timestamp) -> Option<json::Value> {
+		match (&self.metric, &self.path) {
+			(Some(AlertMetric::MemoryUsage), _) => memory_usage(&response.data).map(|m| json::json!(m)),
+			(Some(AlertMetric::OfflineSeconds), _) => Some(json::json!((now - response.timestamp).num_seconds())),
+			(None, Some(path)) => jsonpath::get(&response.data, path).cloned(),
+			(None, None) => None,
+		}
+	}
+}
+
+
+/// a rule whose condition holds for a node
+struct Pending {
+	since: Timestamp,
+	/// set once the condition held for the configured duration
+	firing: Option<Alert>,
+}
+
+
+/// the last response of a node, also after it was removed from the buffer
+struct Seen {
+	timestamp: Timestamp,
+	hostname: Option<String>,
+}
+
+
+/// Tracks the state of every rule for every node. Only changes between
+/// firing and resolved are reported, so every alert is sent once.
+pub struct AlertEngine {
+	rules: Vec<AlertRule>,
+	pending: HashMap<(usize, NodeId), Pending>,
+	seen: HashMap<NodeId, Seen>,
+	check_interval: Duration,
+	/// seconds
+	forget_after: u64,
+	last_check: Instant,
+}
+
+impl AlertEngine {
+	pub fn new(config: Alerts) -> Self {
+		let rules = config
+			.rules
+			.into_iter()
+			.filter(|r| {
+				if r.path.is_none() && r.metric.is_none() {
+					warn!("alert rule {} has neither a path nor a metric. ignoring it", r.name);
+					return false;
+				}
+				true
+			})
+			.collect();
+
+		Self {
+			rules,
+			pending: HashMap::new(),
+			seen: HashMap::new(),
+			check_interval: Duration::from_secs(config.check_interval),
+			forget_after: config.forget_after,
+			last_check: Instant::now(),
+		}
+	}
+
+	/// check all rules against a single response
+	pub fn evaluate(&mut self, response: &NodeResponse, now: Timestamp) -> Vec<Alert> {
+		let mut changes = vec![];
+
+		let seen = self.seen.entry(response.nodeid.clone()).or_insert(Seen {
+			timestamp: response.timestamp,
+			hostname: None,
+		});
+		seen.timestamp = seen.timestamp.max(response.timestamp);
+		if let Some(hostname) = hostname(response) {
+			seen.hostname = Some(hostname);
+		}
+		let hostname = seen.hostname.clone();
+
+		for (i, rule) in self.rules.iter().enumerate() {
+			if rule.applies_to(&response.nodeid) {
+				let value = rule.value(response, now);
+				changes.extend(update(&mut self.pending, i, rule, &response.nodeid, &hostname, value, now));
+			}
+		}
+
+		changes
+	}
+
+	/// Checks all nodes once per `check_interval`, so alerts on nodes that
+	/// stopped responding fire as well.
+	///
+	/// Nodes that were removed from the buffer keep their alerts. Only
+	/// `offline_seconds` can still change for them.
+	pub fn check_if_due<'a, I>(&mut self, responses: I, now: Timestamp) -> Vec<Alert>
+	where
+		I: IntoIterator<Item = &'a NodeResponse>,
+	{
+		if self.rules.is_empty() || self.last_check.elapsed() < self.check_interval {
+			return vec![];
+		}
+		self.last_check = Instant::now();
+
+		let mut changes = vec![];
+		let mut buffered = HashSet::new();
+		for response in responses {
+			buffered.insert(response.nodeid.clone());
+			changes.extend(self.evaluate(response, now));
+		}
+
+		for (nodeid, seen) in self.seen.iter().filter(|(id, _)| !buffered.contains(*id)) {
+			for (i, rule) in self.rules.iter().enumerate() {
+				if rule.metric == Some(AlertMetric::OfflineSeconds) && rule.applies_to(nodeid) {
+					let value = json::json!((now - seen.timestamp).num_seconds());
+					changes.extend(update(&mut self.pending, i, rule, nodeid, &seen.hostname, Some(value), now));
+				}
+			}
+		}
+
+		let forget_after = self.forget_after as i64;
+		let gone: Vec<NodeId> = self
+			.seen
+			.iter()
+			.filter(|(id, seen)| !buffered.contains(*id) && (now - seen.timestamp).num_seconds() > forget_after)
+			.map(|(id, _)| id.clone())
+			.collect();
+		for nodeid in gone {
+			changes.extend(self.forget(&nodeid, now));
+		}
+
+		changes
+	}
+
+	/// Forget a node that is gone for good. Its firing alerts are resolved,
+	/// as nobody would ever resolve them otherwise.
+	fn forget(&mut self, nodeid: &str, now: Timestamp) -> Vec<Alert> {
+		debug!("forgetting {} for alerts", nodeid);
+		self.seen.remove(nodeid);
+
+		let keys: Vec<(usize, NodeId)> = self.pending.keys().filter(|(_, id)| id == nodeid).cloned().collect();
+		keys.into_iter()
+			.filter_map(|key| self.pending.remove(&key)?.firing)
+			.map(|alert| {
+				info!("alert {} resolved for {}, it's gone for too long", alert.rule, nodeid);
+				Alert {
+					state: AlertState::Resolved,
+					timestamp: now,
+					..alert
+				}
+			})
+			.collect()
+	}
+
+	/// all alerts that are currently firing
+	pub fn firing(&self) -> Vec<Alert> {
+		let mut firing: Vec<Alert> = self.pending.values().filter_map(|p| p.firing.clone()).collect();
+		firing.sort_by(|a, b| (&a.rule, &a.nodeid).cmp(&(&b.rule, &b.nodeid)));
+		firing
+	}
+}
+
+
+/// Applies a new value of `rule` and returns the alert if it fired or resolved.
+/// A missing value changes nothing.
+fn update(
+	pending: &mut HashMap<(usize, NodeId), Pending>,
+	i: usize,
+	rule: &AlertRule,
+	nodeid: &str,
+	hostname: &Option<String>,
+	value: Option<json::Value>,
+	now: Timestamp,
+) -> Option<Alert> {
+	let key = (i, nodeid.to_string());
+
+	match value {
+		Some(value) if rule.op.check(&value, &rule.value) => {
+			let pending = pending.entry(key).or_insert(Pending {
+				since: now,
+				firing: None,
+			});
+
+			if pending.firing.is_none() && (now - pending.since).num_seconds() >= rule.duration as i64 {
+				let alert = Alert {
+					rule: rule.name.clone(),
+					nodeid: nodeid.to_string(),
+					hostname: hostname.clone(),
+					state: AlertState::Firing,
+					value,
+					op: rule.op,
+					threshold: rule.value.clone(),
+					since: pending.since,
+					timestamp: now,
+				};
+				info!("alert {} is firing for {}", rule.name, nodeid);
+				pending.firing = Some(alert.clone());
+				return Some(alert);
+			}
+			None
+		}
+		Some(value) => match pending.remove(&key) {
+			Some(Pending { firing: Some(alert), .. }) => {
+				info!("alert {} resolved for {}", rule.name, nodeid);
+				Some(Alert {
+					state: AlertState::Resolved,
+					value,
+					timestamp: now,
+					..alert
+				})
+			}
+			_ => None,
+		},
+		None => None,
+	}
+}
+
+
+fn hostname(response: &NodeResponse) -> Option<String> {
+	response.data.pointer("/nodeinfo/hostname").and_then(|h| h.as_str()).map(|h| h.to_string())
+}
+
+
+/// POSTs every alert as json to `url` without blocking the collector
+pub fn spawn_webhook(url: String) -> Sender<Alert> {
+	let (tx, rx) = channel::bounded::<Alert>(WEBHOOK_QUEUE);
+
+	thread::spawn(move || {
+		let agent = ureq::AgentBuilder::new().timeout(WEBHOOK_TIMEOUT).build();
+
+		for alert in rx {
+			let body = json::to_vec(&alert).unwrap();
+			if let Err(e) = agent.post(&url).set("Content-Type", "application/json").send_bytes(&body) {
+				error!("cannot send alert {} for {} to webhook: {}", alert.rule, alert.nodeid, e);
+			}
+		}
+	});
+
+	tx
+}
+
+/// hand an alert to the webhook thread
+pub fn send_webhook(webhook: &Sender<Alert>, alert: Alert) {
+	if let Err(TrySendError::Full(alert)) = webhook.try_send(alert) {
+		warn!("webhook is too slow. dropping alert {} for {}", alert.rule, alert.nodeid);
+	}
+}
+
+
+
+#[test]
+fn alerts_fire_once_and_resolve() {
+	let config: Alerts = serde_yaml::from_str(
+		r#"
+rules:
+  - name: high_load
+    path: statistics.loadavg
+    op: ">"
+    value: 4
+    for: 600
+"#,
+	)
+	.unwrap();
+	let mut engine = AlertEngine::new(config);
+
+	let start = chrono::Utc::now();
+	let response = |load: f64| NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: start,
+		data: json::json!({"statistics": {"loadavg": load}}),
+	};
+	let later = |minutes: i64| start + chrono::Duration::minutes(minutes);
+
+	assert!(engine.evaluate(&response(5.0), later(0)).is_empty());
+	assert!(engine.evaluate(&response(5.0), later(5)).is_empty());
+
+	let firing = engine.evaluate(&response(6.0), later(10));
+	assert_eq!(firing.len(), 1);
+	assert_eq!(firing[0].state, AlertState::Firing);
+	assert_eq!(firing[0].since, later(0));
+	assert!(engine.evaluate(&response(6.0), later(11)).is_empty());
+	assert_eq!(engine.firing().len(), 1);
+
+	let resolved = engine.evaluate(&response(1.0), later(12));
+	assert_eq!(resolved.len(), 1);
+	assert_eq!(resolved[0].state, AlertState::Resolved);
+	assert!(engine.firing().is_empty());
+}
+
+#[test]
+fn alerts_outlive_the_buffer() {
+	let config: Alerts = serde_yaml::from_str(
+		r#"
+check_interval: 0
+rules:
+  - name: offline
+    metric: offline_seconds
+    op: ">"
+    value: 300
+  - name: no_clients
+    path: statistics.clients.total
+    op: "=="
+    value: 0
+"#,
+	)
+	.unwrap();
+	let mut engine = AlertEngine::new(config);
+
+	let start = chrono::Utc::now();
+	let later = |minutes: i64| start + chrono::Duration::minutes(minutes);
+	let response = NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: start,
+		data: json::json!({"statistics": {"clients": {"total": 0}}}),
+	};
+
+	assert_eq!(engine.evaluate(&response, later(0)).len(), 1);
+
+	// a response without statistics doesn't resolve the alert
+	let nodeinfo = NodeResponse {
+		data: json::json!({"nodeinfo": {}}),
+		..response.clone()
+	};
+	assert!(engine.evaluate(&nodeinfo, later(1)).is_empty());
+	assert_eq!(engine.firing().len(), 1);
+
+	// the node was removed from the buffer, but goes offline anyway
+	assert!(engine.check_if_due(vec![], later(2)).is_empty());
+	let offline = engine.check_if_due(vec![], later(6));
+	assert_eq!(offline.len(), 1);
+	assert_eq!(offline[0].rule, "offline");
+	assert_eq!(engine.firing().len(), 2);
+
+	let back = NodeResponse {
+		timestamp: later(7),
+		..nodeinfo
+	};
+	let resolved = engine.evaluate(&back, later(7));
+	assert_eq!(resolved.len(), 1);
+	assert_eq!(resolved[0].state, AlertState::Resolved);
+	assert_eq!(engine.firing()[0].rule, "no_clients");
+}
+
+#[test]
+fn gone_nodes_are_forgotten() {
+	let config: Alerts = serde_yaml::from_str(
+		r#"
+check_interval: 0
+forget_after: 3600
+rules:
+  - name: offline
+    metric: offline_seconds
+    op: ">"
+    value: 300
+"#,
+	)
+	.unwrap();
+	let mut engine = AlertEngine::new(config);
+
+	let start = chrono::Utc::now();
+	let later = |minutes: i64| start + chrono::Duration::minutes(minutes);
+	let response = NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: start,
+		data: json::json!({}),
+	};
+	engine.evaluate(&response, later(0));
+
+	assert_eq!(engine.check_if_due(vec![], later(10)).len(), 1);
+	assert!(engine.check_if_due(vec![], later(60)).is_empty());
+
+	let forgotten = engine.check_if_due(vec![], later(61));
+	assert_eq!(forgotten.len(), 1);
+	assert_eq!(forgotten[0].state, AlertState::Resolved);
+	assert!(engine.firing().is_empty());
+	assert!(engine.seen.is_empty());
+
+	// still buffered nodes are kept, however old their response is
+	engine.evaluate(&response, later(0));
+	assert!(!engine.check_if_due(vec![&response], later(120)).is_empty());
+	assert_eq!(engine.seen.len(), 1);
+}
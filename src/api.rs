@@ -0,0 +1,217 @@
+//! rest api for single nodes
+//!
+//! - `/nodes`: a short summary of all nodes
+//! - `/nodes/{nodeid}`: the whole response
+//! - `/nodes/{nodeid}/{category}[/...]`: a part of the response like `statistics/traffic` or `meta`
+//! - `POST /nodes/{nodeid}/refresh`: request the node right now
+use crate::collector::{CollectorHandle, Snapshot};
+use crate::meshviewer::format_time;
+use crate::privacy::Redactor;
+use crate::query::percent_decode;
+use crate::NodeResponse;
+use crate::CONFIG;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde_json as json;
+use std::io::Cursor;
+use tiny_http::{Header, Method, Request, Response};
+
+pub const JSON: &str = "application/json";
+
+
+pub fn json_header() -> Header {
+	Header::from_bytes("Content-Type", JSON).unwrap()
+}
+
+/// `{"status": 404, "error": "..."}`
+pub fn error_response(status: u16, message: &str) -> Response<Cursor<Vec<u8>>> {
+	let body = json::to_vec(&json::json!({
+		"status": status,
+		"error": message,
+	}))
+	.unwrap();
+
+	Response::from_data(body).with_status_code(status).with_header(json_header())
+}
+
+pub fn respond_error(req: Request, status: u16, message: &str) {
+	req.respond(error_response(status, message)).ok();
+}
+
+pub fn respond_not_allowed(req: Request, allow: &str) {
+	let allow = Header::from_bytes("Allow", allow).unwrap();
+	req.respond(error_response(405, "method not allowed").with_header(allow)).ok();
+}
+
+/// for unredacted data, that shared caches must not hand to anyone else
+pub fn private_header() -> Header {
+	Header::from_bytes("Cache-Control", "private").unwrap()
+}
+
+fn respond_json(req: Request, value: &json::Value, private: bool) {
+	let mut res = Response::from_data(json::to_vec(value).unwrap()).with_header(json_header());
+	if private {
+		res.add_header(private_header());
+	}
+	req.respond(res).ok();
+}
+
+
+/// summary of all nodes, rendered once per snapshot
+pub fn render_nodes(snapshot: &Snapshot) -> Vec<u8> {
+	let mut nodes: Vec<json::Value> = snapshot.responses().iter().map(|r| summary(r)).collect();
+	nodes.sort_by(|a, b| a["nodeid"].as_str().cmp(&b["nodeid"].as_str()));
+
+	json::to_vec(&nodes).unwrap()
+}
+
+fn summary(response: &NodeResponse) -> json::Value {
+	let categories: Vec<&String> = response.data.as_object().iter().flat_map(|d| d.keys()).collect();
+
+	json::json!({
+		"nodeid": response.nodeid,
+		"hostname": response.data.pointer("/nodeinfo/hostname"),
+		"online": response.is_online(),
+		"lastseen": format_time(response.timestamp),
+		"categories": categories,
+	})
+}
+
+/// `path` is everything after `/nodes/`
+/// `private` if the snapshot isn't redacted
+pub fn handle_node(req: Request, snapshot: &Snapshot, path: &str, private: bool) {
+	if !matches!(req.method(), Method::Get | Method::Head) {
+		return respond_not_allowed(req, "GET, HEAD");
+	}
+
+	// a query string isn't used here
+	let path = path.split('?').next().unwrap_or_default();
+	let mut segments = path.trim_end_matches('/').split('/').map(percent_decode);
+	let nodeid = segments.next().unwrap_or_default();
+	let keys: Vec<String> = segments.collect();
+
+	let response = match snapshot.response(&nodeid) {
+		Some(r) => r,
+		None => return respond_error(req, 404, &format!("unknown node {}", nodeid)),
+	};
+
+	if keys.is_empty() {
+		return respond_json(req, &json::to_value(response).unwrap(), private);
+	}
+
+	// every segment is a literal key, even with a `~` or an encoded `/`
+	let pointer: String = keys.iter().map(|k| format!("/{}", k.replace('~', "~0").replace('/', "~1"))).collect();
+	match response.data.pointer(&pointer) {
+		Some(value) => respond_json(req, value, private),
+		None => respond_error(req, 404, &format!("node {} has no {}", nodeid, keys.join("/"))),
+	}
+}
+
+
+/// Request a node right now instead of waiting for the next interval.
+/// `nodeid` is the one the client knows, so it may be redacted.
+pub fn refresh(collector: &CollectorHandle, redactor: &Redactor, nodeid: &str) -> bool {
+	let snapshot = collector.snapshot();
+
+	match snapshot.responses().iter().find(|r| redactor.nodeid(&r.nodeid) == nodeid) {
+		Some(r) => {
+			debug!("refreshing {}", r.nodeid);
+			collector.requester().request(&r.remote.to_string(), &CONFIG.requestd.categories);
+			true
+		}
+		None => false,
+	}
+}
+
+pub fn handle_refresh(req: Request, collector: &CollectorHandle, redactor: &Redactor, nodeid: &str) {
+	if !refresh(collector, redactor, nodeid) {
+		return respond_error(req, 404, &format!("unknown node {}", nodeid));
+	}
+
+	let body = json::to_vec(&json::json!({"status": 202, "nodeid": nodeid})).unwrap();
+	req.respond(Response::from_data(body).with_status_code(202).with_header(json_header())).ok();
+}
+
+
+
+#[test]
+fn node_lookup() {
+	use std::sync::Arc;
+
+	let response = NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::Utc::now(),
+		data: json::json!({
+			"nodeinfo": {"hostname": "ffhl-test"},
+			"statistics": {"traffic": {"rx": {"bytes": 100}}},
+			"meta": {"a/b~1": "odd key"},
+		}),
+	};
+	let snapshot = Snapshot::new(
+		1,
+		chrono::Utc::now(),
+		vec![Arc::new(response)],
+		180,
+		Default::default(),
+		Default::default(),
+		vec![],
+		vec![],
+	);
+
+	let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+	let base = format!("http://{}/nodes/", server.server_addr());
+	let call = |method: &'static str, path: &str| {
+		let url = format!("{}{}", base, path);
+		let client = std::thread::spawn(move || match ureq::request(method, &url).call() {
+			Ok(res) | Err(ureq::Error::Status(_, res)) => {
+				let allow = res.header("Allow").map(|a| a.to_string());
+				(res.status(), allow, json::from_str::<json::Value>(&res.into_string().unwrap()).unwrap())
+			}
+			Err(e) => panic!("{}", e),
+		});
+
+		let req = server.recv().unwrap();
+		let path = req.url()["/nodes/".len()..].to_string();
+		handle_node(req, &snapshot, &path, false);
+		client.join().unwrap()
+	};
+
+	let (status, _, node) = call("GET", "c04a00dd692a");
+	assert_eq!(status, 200);
+	assert_eq!(node["data"]["nodeinfo"]["hostname"], "ffhl-test");
+
+	let (status, _, rx) = call("GET", "c04a00dd692a/statistics/traffic/rx/");
+	assert_eq!(status, 200);
+	assert_eq!(rx, json::json!({"bytes": 100}));
+
+	// decoded, and the query is no part of the path
+	let (status, _, rx) = call("GET", "c04a00dd692%61/statistics/traffic/rx?pretty=1");
+	assert_eq!(status, 200);
+	assert_eq!(rx, json::json!({"bytes": 100}));
+
+	let (status, _, odd) = call("GET", "c04a00dd692a/meta/a%2Fb~1");
+	assert_eq!(status, 200);
+	assert_eq!(odd, "odd key");
+
+	let (status, _, error) = call("GET", "c04a00dd692a/statistics/wifi");
+	assert_eq!(status, 404);
+	assert_eq!(error["error"], "node c04a00dd692a has no statistics/wifi");
+
+	let (status, _, error) = call("GET", "000000000000");
+	assert_eq!(status, 404);
+	assert_eq!(error["error"], "unknown node 000000000000");
+
+	let (status, allow, _) = call("DELETE", "c04a00dd692a");
+	assert_eq!(status, 405);
+	assert_eq!(allow.as_deref(), Some("GET, HEAD"));
+
+	// unredacted nodes are for this client only
+	let url = format!("{}c04a00dd692a", base);
+	let client = std::thread::spawn(move || {
+		let res = ureq::get(&url).call().unwrap();
+		res.header("Cache-Control").map(|c| c.to_string())
+	});
+	handle_node(server.recv().unwrap(), &snapshot, "c04a00dd692a", true);
+	assert_eq!(client.join().unwrap().as_deref(), Some("private"));
+}
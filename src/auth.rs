@@ -0,0 +1,113 @@
+//! bearer tokens and basic auth for the web endpoint
+use crate::config::{AccessToken, Scope, User, WebEndpoint};
+use sha2::{Digest, Sha256};
+
+
+/// result of checking the `Authorization` header
+#[derive(Debug, PartialEq)]
+pub enum Access {
+	/// no credentials
+	Anonymous,
+	Granted(Scope),
+	/// unknown token, user or wrong password
+	Invalid,
+}
+
+
+pub struct Auth {
+	tokens: Vec<AccessToken>,
+	users: Vec<User>,
+	required: bool,
+}
+
+impl Auth {
+	pub fn new(conf: &WebEndpoint) -> Self {
+		Self {
+			tokens: conf.tokens.clone(),
+			users: conf.users.clone(),
+			required: conf.require_auth,
+		}
+	}
+
+	/// anonymous requests are not allowed on the public listener
+	pub fn is_required(&self) -> bool {
+		self.required
+	}
+
+	/// value of the `WWW-Authenticate` header of a 401
+	pub fn challenge(&self) -> &'static str {
+		if self.users.is_empty() {
+			"Bearer realm=\"requestd\""
+		} else {
+			"Basic realm=\"requestd\", charset=\"UTF-8\""
+		}
+	}
+
+	pub fn authenticate(&self, authorization: Option<&str>) -> Access {
+		let header = match authorization {
+			Some(h) => h.trim(),
+			None => return Access::Anonymous,
+		};
+
+		let (scheme, credentials) = header.split_once(' ').unwrap_or((header, ""));
+		let credentials = credentials.trim();
+
+		let scope = if scheme.eq_ignore_ascii_case("bearer") {
+			self.tokens.iter().find(|t| constant_time_eq(t.token.as_bytes(), credentials.as_bytes())).map(|t| t.scope)
+		} else if scheme.eq_ignore_ascii_case("basic") {
+			self.basic(credentials)
+		} else {
+			None
+		};
+
+		match scope {
+			Some(scope) => Access::Granted(scope),
+			None => Access::Invalid,
+		}
+	}
+
+	fn basic(&self, credentials: &str) -> Option<Scope> {
+		let decoded = String::from_utf8(base64::decode(credentials).ok()?).ok()?;
+		let (name, password) = decoded.split_once(':')?;
+
+		let hash = format!("{:x}", Sha256::digest(password.as_bytes()));
+		self.users
+			.iter()
+			.find(|u| u.name == name && constant_time_eq(u.password_sha256.to_lowercase().as_bytes(), hash.as_bytes()))
+			.map(|u| u.scope)
+	}
+}
+
+
+/// doesn't leak how many bytes of a token were right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+
+#[test]
+fn bearer_and_basic() {
+	let auth = Auth {
+		tokens: vec![AccessToken {
+			token: "s3cr3t".to_string(),
+			scope: Scope::Full,
+		}],
+		users: vec![User {
+			name: "noc".to_string(),
+			// sha256 of "password"
+			password_sha256: "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8".to_string(),
+			scope: Scope::Admin,
+		}],
+		required: false,
+	};
+
+	assert_eq!(auth.authenticate(None), Access::Anonymous);
+	assert_eq!(auth.authenticate(Some("Bearer s3cr3t")), Access::Granted(Scope::Full));
+	assert_eq!(auth.authenticate(Some("Bearer s3cr3")), Access::Invalid);
+	// noc:password
+	assert_eq!(auth.authenticate(Some("Basic bm9jOnBhc3N3b3Jk")), Access::Granted(Scope::Admin));
+	// noc:wrong
+	assert_eq!(auth.authenticate(Some("Basic bm9jOndyb25n")), Access::Invalid);
+	assert!(Scope::Admin > Scope::Full && Scope::Full > Scope::Public);
+}
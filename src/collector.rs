@@ -1,26 +1,55 @@
 #![allow(unused_must_use)]
 
+use crate::alerts::{self, Alert, AlertEngine};
+use crate::config::{Alerts, DropPolicy, EventQueue, ExpectedNode, Requestd};
+use crate::encoding::Encoding;
+use crate::events::{Event, NodeEvent};
+use crate::inventory::{self, InventoryNode, InventoryTracker};
 use crate::multicast::RequesterService;
+use crate::privacy::Redactor;
+use crate::topology::Topology;
+use crate::processor::{Pipeline, Processor};
+use crate::reboots::{RebootHistory, RebootTracker};
 use crate::NodeId;
 use crate::CONFIG;
 use crate::NodeResponse;
+use crate::Timestamp;
+use chrono::Utc;
 use crossbeam;
-use crossbeam::channel::{self, Receiver, Sender};
+use crossbeam::channel::{self, Receiver, Sender, SendTimeoutError, TrySendError};
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
+use serde::Serialize;
 use serde_json as json;
 use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::io;
-use std::time::Instant;
+use std::process;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+use std::time::{Duration, Instant};
 
+/// how often a blocked send checks if the subscriber is still alive
+const BLOCKING_SEND_RECHECK: Duration = Duration::from_millis(500);
 
-#[derive(Clone)]
+
+type Subscribers = Arc<Mutex<Vec<Subscriber>>>;
+
+
+/// Owns the response buffer. It lives on the thread that processes the
+/// responses, everybody else uses a `CollectorHandle`.
 pub struct Collector {
 	received_counter: usize,
 	requester: RequesterService,
-	buffer: ResponseBuffer,
-	event_senders: Vec<Sender<NodeResponse>>,
+	pipeline: Pipeline,
+	nodes: NodeState,
+	webhook: Option<Sender<Alert>>,
+	subscribers: Subscribers,
+	snapshot: Arc<RwLock<Arc<Snapshot>>>,
+	/// the buffer changed since the last snapshot was published
+	dirty: bool,
+	last_publish: Instant,
 }
 
 
@@ -28,11 +57,33 @@ pub struct Collector {
 impl Collector {
 	/// Starts a collector thread that also checks the database for offline nodes
 	pub fn new(requester: RequesterService) -> Self {
+		let expected = match &CONFIG.inventory {
+			Some(i) => inventory::load(i).unwrap_or_else(|e| {
+				error!("cannot load inventory: {}", e);
+				process::exit(1);
+			}),
+			None => vec![],
+		};
+
 		Self {
 			requester,
 			received_counter: 0,
-			buffer: ResponseBuffer::new(CONFIG.requestd.clone().retention),
-			event_senders: vec![]
+			pipeline: Pipeline::from_config(&CONFIG.requestd.processors, CONFIG.requestd.interval),
+			nodes: NodeState::new(&CONFIG.requestd, CONFIG.alerts.clone().unwrap_or_default(), expected),
+			webhook: CONFIG.alerts.as_ref().and_then(|a| a.webhook.clone()).map(alerts::spawn_webhook),
+			subscribers: Arc::new(Mutex::new(vec![])),
+			snapshot: Arc::new(RwLock::new(Arc::new(Snapshot::empty()))),
+			dirty: false,
+			last_publish: Instant::now(),
+		}
+	}
+
+	/// get a handle that can be passed to other threads
+	pub fn handle(&self) -> CollectorHandle {
+		CollectorHandle {
+			requester: self.requester.clone(),
+			subscribers: self.subscribers.clone(),
+			snapshot: self.snapshot.clone(),
 		}
 	}
 
@@ -45,32 +96,494 @@ impl Collector {
 	}
 
 
+	/// append a processor to the configured ones
+	pub fn add_processor(&mut self, processor: Box<dyn Processor>) {
+		self.pipeline.push(processor);
+	}
+
 	pub fn receive(&mut self, response: NodeResponse) {
-		// *self.received_counter.lock().unwrap() += 1;
-		self.notify_receivers(response.clone());
-		self.buffer.receive(response.clone());
+		self.received_counter += 1;
+
+		let response = match self.pipeline.process(response) {
+			Some(r) => r,
+			None => return,
+		};
+
+		self.notify_receivers(response.clone().into());
+		let events = self.nodes.receive(response, Utc::now());
+		self.notify_node_events(events);
+		self.dirty = true;
+	}
+
+	fn notify_node_events(&mut self, events: Vec<NodeEvent>) {
+		for event in events {
+			if let (NodeEvent::Alert(alert), Some(webhook)) = (&event, &self.webhook) {
+				alerts::send_webhook(webhook, alert.clone());
+			}
+			self.notify_receivers(event.into());
+			self.dirty = true;
+		}
 	}
 
-	fn notify_receivers(&self, msg: NodeResponse) {
+	fn notify_receivers(&mut self, msg: Event) {
+		let mut subscribers = self.subscribers.lock().unwrap();
+
+		subscribers.retain(|s| {
+			if s.is_alive() {
+				return true;
+			}
+
+			info!("removing disconnected subscriber {}", s.name);
+			false
+		});
+
 		// send data to all subscribed listeners
-		for sender in &self.event_senders {
-			sender.try_send(msg.clone());
+		for subscriber in subscribers.iter() {
+			subscriber.send(msg.clone());
 		}
 	}
 
-	pub fn all_responses(&mut self) -> Vec<NodeResponse> {
-		let all_responses = self.buffer.get_all_responses();
-		all_responses
+	/// housekeeping. Must be called regularly, even if no responses arrive.
+	///
+	/// Purges old responses and publishes a new snapshot if the buffer changed.
+	pub fn tick(&mut self) {
+		let (purged, events) = self.nodes.tick(Utc::now());
+		self.dirty |= purged;
+		self.notify_node_events(events);
+
+		if self.dirty && self.last_publish.elapsed() >= Duration::from_millis(CONFIG.requestd.publish_interval) {
+			self.publish_snapshot();
+		}
+	}
+
+	fn publish_snapshot(&mut self) {
+		let t = Instant::now();
+		let generation = self.snapshot.read().unwrap().generation + 1;
+
+		let snapshot = Arc::new(self.nodes.snapshot(generation));
+
+		*self.snapshot.write().unwrap() = snapshot;
+		self.dirty = false;
+		self.last_publish = Instant::now();
+		trace!("published snapshot {} in {}ms", generation, t.elapsed().as_millis());
 	}
 
 	pub fn get_num_received(&self) -> usize {
 		self.received_counter
 	}
+}
+
 
-	pub fn get_events_receiver(&mut self) -> Receiver<NodeResponse> {
-		let (tx, rx) = channel::unbounded();
-		self.event_senders.push(tx);
-		rx
+/// Everything the collector knows about the nodes.
+///
+/// Kept apart from the requester and the subscribers, so it works without a network.
+struct NodeState {
+	buffer: ResponseBuffer,
+	offline_after: u64,
+	reboots: RebootTracker,
+	alerts: AlertEngine,
+	inventory: InventoryTracker,
+}
+
+impl NodeState {
+	fn new(config: &Requestd, alerts: Alerts, expected: Vec<ExpectedNode>) -> Self {
+		Self {
+			buffer: ResponseBuffer::new(config.retention, config.clean_interval),
+			offline_after: config.offline_after,
+			reboots: RebootTracker::new(config.reboot_history, config.offline_after),
+			alerts: AlertEngine::new(alerts),
+			inventory: InventoryTracker::new(expected, config.offline_after),
+		}
+	}
+
+	/// store a processed response and return the events it caused
+	fn receive(&mut self, response: NodeResponse, now: Timestamp) -> Vec<NodeEvent> {
+		let mut events = vec![];
+
+		if let Some(reboot) = self.reboots.observe(&response) {
+			events.push(NodeEvent::Rebooted(reboot));
+		}
+		self.inventory.seen(&response);
+		events.extend(self.alerts.evaluate(&response, now).into_iter().map(NodeEvent::Alert));
+
+		self.buffer.receive(response);
+		events
+	}
+
+	/// Purges old responses and checks all nodes for alerts and inventory changes.
+	///
+	/// Returns if responses were purged and the resulting events.
+	fn tick(&mut self, now: Timestamp) -> (bool, Vec<NodeEvent>) {
+		let purged = self.buffer.clean_if_due() > 0;
+		self.reboots.expire(now);
+
+		let mut events: Vec<NodeEvent> =
+			self.alerts.check_if_due(self.buffer.responses.values().map(|r| &**r), now).into_iter().map(NodeEvent::Alert).collect();
+
+		if !self.inventory.is_empty() {
+			events.extend(self.inventory.update(now).into_iter().map(NodeEvent::Inventory));
+		}
+
+		(purged, events)
+	}
+
+	fn snapshot(&self, generation: u64) -> Snapshot {
+		Snapshot::new(
+			generation,
+			Utc::now(),
+			self.buffer.get_all_responses(),
+			self.offline_after,
+			self.buffer.first_seen.clone(),
+			self.reboots.rebooted(),
+			self.alerts.firing(),
+			self.inventory.report(),
+		)
+	}
+}
+
+
+/// rendered kind and compression of a cached body
+type EncodedKey = (&'static str, Encoding);
+
+
+/// Gives other threads access to the collector without blocking it.
+#[derive(Clone)]
+pub struct CollectorHandle {
+	requester: RequesterService,
+	subscribers: Subscribers,
+	snapshot: Arc<RwLock<Arc<Snapshot>>>,
+}
+
+impl CollectorHandle {
+	/// the most recently published state of the response buffer
+	pub fn snapshot(&self) -> Arc<Snapshot> {
+		self.snapshot.read().unwrap().clone()
+	}
+
+	pub fn requester(&self) -> &RequesterService {
+		&self.requester
+	}
+
+	/// subscribe to all new responses and node events.
+	///
+	/// The subscriber is removed as soon as the returned receiver is dropped.
+	pub fn get_events_receiver(&self, name: &str, queue: EventQueue) -> EventReceiver {
+		debug!("new subscriber {} ({:?}, capacity {})", name, queue.policy, queue.capacity);
+		let (subscriber, receiver) = Subscriber::new(name, queue);
+		self.subscribers.lock().unwrap().push(subscriber);
+
+		receiver
+	}
+
+	pub fn subscriber_stats(&self) -> Vec<SubscriberStats> {
+		self.subscribers.lock().unwrap().iter().filter(|s| s.is_alive()).map(|s| s.stats()).collect()
+	}
+}
+
+
+/// An immutable copy of the response buffer.
+///
+/// Readers share the same snapshot until the collector publishes a new one,
+/// so the json is only serialized once per snapshot.
+pub struct Snapshot {
+	generation: u64,
+	created: Timestamp,
+	/// shared with the buffer, responses are never changed once stored
+	responses: Vec<Arc<NodeResponse>>,
+	/// nodes that didn't respond for this many seconds are offline
+	offline_after: u64,
+	first_seen: HashMap<NodeId, Timestamp>,
+	reboots: HashMap<NodeId, RebootHistory>,
+	/// alerts that are firing
+	alerts: Vec<Alert>,
+	/// status of all expected nodes
+	inventory: Vec<InventoryNode>,
+	/// position of every node in `responses`
+	index: OnceLock<HashMap<NodeId, usize>>,
+	json: OnceLock<Vec<u8>>,
+	topology: OnceLock<Topology>,
+	/// other formats rendered from this snapshot
+	rendered: Mutex<HashMap<&'static str, Arc<Vec<u8>>>>,
+	/// compressed bodies for http
+	encoded: Mutex<HashMap<EncodedKey, Arc<Vec<u8>>>>,
+}
+
+impl Snapshot {
+	#[allow(clippy::too_many_arguments)]
+	pub fn new(
+		generation: u64,
+		created: Timestamp,
+		responses: Vec<Arc<NodeResponse>>,
+		offline_after: u64,
+		first_seen: HashMap<NodeId, Timestamp>,
+		reboots: HashMap<NodeId, RebootHistory>,
+		alerts: Vec<Alert>,
+		inventory: Vec<InventoryNode>,
+	) -> Self {
+		Self {
+			generation,
+			created,
+			responses,
+			offline_after,
+			first_seen,
+			reboots,
+			alerts,
+			inventory,
+			index: OnceLock::new(),
+			json: OnceLock::new(),
+			topology: OnceLock::new(),
+			rendered: Mutex::new(HashMap::new()),
+			encoded: Mutex::new(HashMap::new()),
+		}
+	}
+
+	fn empty() -> Self {
+		Self::new(0, Utc::now(), vec![], CONFIG.requestd.offline_after, HashMap::new(), HashMap::new(), vec![], vec![])
+	}
+
+	/// increases every time the buffer changed
+	pub fn generation(&self) -> u64 {
+		self.generation
+	}
+
+	pub fn created(&self) -> Timestamp {
+		self.created
+	}
+
+	/// the node responded within `offline_after` of this snapshot
+	pub fn is_online(&self, response: &NodeResponse) -> bool {
+		response.responded_within(self.offline_after)
+	}
+
+	pub fn responses(&self) -> &[Arc<NodeResponse>] {
+		&self.responses
+	}
+
+	pub fn response(&self, nodeid: &str) -> Option<&NodeResponse> {
+		let index = self
+			.index
+			.get_or_init(|| self.responses.iter().enumerate().map(|(i, r)| (r.nodeid.clone(), i)).collect());
+
+		index.get(nodeid).map(|&i| &*self.responses[i])
+	}
+
+	/// when the node was first seen since it entered the buffer
+	pub fn first_seen(&self, nodeid: &str) -> Option<Timestamp> {
+		self.first_seen.get(nodeid).copied()
+	}
+
+	/// reboot count and recent reboots of all nodes that rebooted
+	pub fn reboots(&self) -> &HashMap<NodeId, RebootHistory> {
+		&self.reboots
+	}
+
+	/// all alerts that are currently firing
+	pub fn alerts(&self) -> &[Alert] {
+		&self.alerts
+	}
+
+	pub fn inventory(&self) -> &[InventoryNode] {
+		&self.inventory
+	}
+
+	/// all responses as json array
+	pub fn json(&self) -> &[u8] {
+		self.json.get_or_init(|| {
+			let responses: Vec<&NodeResponse> = self.responses.iter().map(|r| &**r).collect();
+			json::to_vec(&responses).unwrap()
+		})
+	}
+
+	/// the mesh topology of this snapshot
+	pub fn topology(&self) -> &Topology {
+		self.topology.get_or_init(|| Topology::new(&self.responses, self.offline_after))
+	}
+
+	/// Render `kind` only once per snapshot.
+	///
+	/// Concurrent readers of the same kind wait for the first one.
+	pub fn rendered<F>(&self, kind: &'static str, render: F) -> Arc<Vec<u8>>
+	where
+		F: FnOnce(&Snapshot) -> Vec<u8>,
+	{
+		let mut rendered = self.rendered.lock().unwrap();
+		rendered.entry(kind).or_insert_with(|| Arc::new(render(self))).clone()
+	}
+
+	/// `body` of `kind` compressed with `encoding`, also only once per snapshot
+	pub fn encoded<F>(&self, kind: &'static str, encoding: Encoding, body: F) -> Arc<Vec<u8>>
+	where
+		F: FnOnce() -> Arc<Vec<u8>>,
+	{
+		let mut encoded = self.encoded.lock().unwrap();
+		encoded.entry((kind, encoding)).or_insert_with(|| Arc::new(encoding.encode(&body()))).clone()
+	}
+
+	/// a copy of this snapshot with personal data removed
+	pub fn redacted(&self, redactor: &Redactor) -> Snapshot {
+		let responses = redactor.redact(&self.responses);
+		// node ids may be hashed as well
+		let first_seen = self
+			.responses
+			.iter()
+			.zip(responses.iter())
+			.filter_map(|(orig, redacted)| self.first_seen(&orig.nodeid).map(|t| (redacted.nodeid.clone(), t)))
+			.collect();
+		let reboots = self
+			.reboots
+			.iter()
+			.map(|(id, history)| (redactor.nodeid(id), redactor.reboot_history(history)))
+			.collect();
+		let alerts = self
+			.alerts
+			.iter()
+			.cloned()
+			.map(|mut a| {
+				a.nodeid = redactor.nodeid(&a.nodeid);
+				a
+			})
+			.collect();
+		let inventory = self
+			.inventory
+			.iter()
+			.cloned()
+			.map(|mut n| {
+				n.nodeid = redactor.nodeid(&n.nodeid);
+				n
+			})
+			.collect();
+
+		Snapshot::new(self.generation, self.created, responses, self.offline_after, first_seen, reboots, alerts, inventory)
+	}
+}
+
+
+/// the collector side of an event subscription
+struct Subscriber {
+	name: String,
+	policy: DropPolicy,
+	sender: Sender<Event>,
+	/// used to discard the oldest event if the queue is full
+	receiver: Receiver<Event>,
+	/// gone as soon as the `EventReceiver` got dropped
+	alive: Weak<()>,
+	dropped: Arc<AtomicU64>,
+}
+
+impl Subscriber {
+	fn new(name: &str, queue: EventQueue) -> (Self, EventReceiver) {
+		let (tx, rx) = channel::bounded(queue.capacity);
+		let alive = Arc::new(());
+		let dropped = Arc::new(AtomicU64::new(0));
+
+		let subscriber = Self {
+			name: name.to_string(),
+			policy: queue.policy,
+			sender: tx,
+			receiver: rx.clone(),
+			alive: Arc::downgrade(&alive),
+			dropped: dropped.clone(),
+		};
+		let receiver = EventReceiver {
+			receiver: rx,
+			dropped,
+			_alive: alive,
+		};
+
+		(subscriber, receiver)
+	}
+
+	fn is_alive(&self) -> bool {
+		self.alive.strong_count() > 0
+	}
+
+	fn send(&self, msg: Event) {
+		let mut msg = msg;
+
+		match self.policy {
+			DropPolicy::DropNewest => {
+				if let Err(TrySendError::Full(_)) = self.sender.try_send(msg) {
+					self.drop_event();
+				}
+			}
+			DropPolicy::DropOldest => {
+				while let Err(TrySendError::Full(m)) = self.sender.try_send(msg) {
+					if self.receiver.try_recv().is_ok() {
+						self.drop_event();
+					}
+					msg = m;
+				}
+			}
+			DropPolicy::Block => loop {
+				match self.sender.send_timeout(msg, BLOCKING_SEND_RECHECK) {
+					Err(SendTimeoutError::Timeout(m)) if self.is_alive() => {
+						trace!("subscriber {} is blocking the collector", self.name);
+						msg = m;
+					}
+					_ => break,
+				}
+			},
+		}
+	}
+
+	fn drop_event(&self) {
+		let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+		// don't flood the log if a subscriber stalls completely
+		if dropped.is_power_of_two() {
+			warn!("subscriber {} is too slow. dropped {} events so far", self.name, dropped);
+		}
+	}
+
+	fn stats(&self) -> SubscriberStats {
+		SubscriberStats {
+			name: self.name.clone(),
+			policy: self.policy,
+			capacity: self.sender.capacity().unwrap_or(0),
+			queued: self.sender.len(),
+			dropped: self.dropped.load(Ordering::Relaxed),
+		}
+	}
+}
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SubscriberStats {
+	pub name: String,
+	pub policy: DropPolicy,
+	pub capacity: usize,
+	pub queued: usize,
+	pub dropped: u64,
+}
+
+
+/// the receiving end of an event subscription
+pub struct EventReceiver {
+	receiver: Receiver<Event>,
+	dropped: Arc<AtomicU64>,
+	_alive: Arc<()>,
+}
+
+impl EventReceiver {
+	/// number of events that were discarded because the queue was full
+	pub fn dropped(&self) -> u64 {
+		self.dropped.load(Ordering::Relaxed)
+	}
+}
+
+impl Deref for EventReceiver {
+	type Target = Receiver<Event>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.receiver
+	}
+}
+
+impl<'a> IntoIterator for &'a EventReceiver {
+	type Item = Event;
+	type IntoIter = channel::Iter<'a, Event>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.receiver.iter()
 	}
 }
 
@@ -104,41 +617,51 @@ impl Display for EventError {
 
 
 
-#[derive(Clone)]
 pub struct ResponseBuffer {
-	responses: HashMap<NodeId, NodeResponse>,
+	responses: HashMap<NodeId, Arc<NodeResponse>>,
+	first_seen: HashMap<NodeId, Timestamp>,
 	// receiver: Receiver<NodeResponse>,
 	max_age: u64,
+	/// seconds between two purges
+	clean_interval: u64,
 	last_clean: Instant,
 }
 
 impl ResponseBuffer {
-	fn new(max_age: u64) -> Self {
+	fn new(max_age: u64, clean_interval: u64) -> Self {
 		Self {
 			responses: HashMap::new(),
+			first_seen: HashMap::new(),
 			// receiver: events,
 			max_age,
+			clean_interval,
 			last_clean: Instant::now(),
 		}
 	}
 
 	fn receive(&mut self, response: NodeResponse) {
-		self.responses.insert(response.nodeid.clone(), response.clone());
+		self.first_seen.entry(response.nodeid.clone()).or_insert(response.timestamp);
+		self.responses.insert(response.nodeid.clone(), Arc::new(response));
 	}
 
-	fn get_all_responses(&mut self) -> Vec<NodeResponse> {
+	/// cheap, the responses are shared with the buffer
+	fn get_all_responses(&self) -> Vec<Arc<NodeResponse>> {
+		self.responses.values().cloned().collect()
+	}
+
+	/// returns the number of purged responses
+	fn clean_if_due(&mut self) -> usize {
 		// cleaning takes up to 100ms (in debug mode)
 		// inly do in once in a while
-		if self.last_clean.elapsed().as_secs() > crate::RESPONSE_CLEANING_MAX  {
-			self.clean_responses();
+		if self.last_clean.elapsed().as_secs() >= self.clean_interval {
+			return self.clean_responses();
 		}
 
-		let all_responses = self.responses.values().map(|n| n.clone()).collect();
-		all_responses
+		0
 	}
 
 	/// searches the database for offline nodes and trigger events
-	fn clean_responses(&mut self) {
+	fn clean_responses(&mut self) -> usize {
 		let t = Instant::now();
 		debug!("checking {} entries for dead responses", self.responses.len());
 
@@ -148,6 +671,7 @@ impl ResponseBuffer {
 			if response.age() > self.max_age {
 				// trace!("purging node: {}", id);
 				self.responses.remove(id);
+				self.first_seen.remove(id);
 				i += 1;
 			}
 		}
@@ -155,6 +679,7 @@ impl ResponseBuffer {
 		self.last_clean = Instant::now();
 		debug!("removed {} entries", i);
 		debug!("cleanup took: {}ms ", t.elapsed().as_millis());
+		i
 	}
 
 	// let collector_c = collector.clone();
@@ -165,3 +690,91 @@ impl ResponseBuffer {
 	// 	}
 	// });
 }
+
+
+
+#[test]
+fn node_state_survives_purges() {
+	let config = Requestd {
+		clean_interval: 0,
+		..Requestd::default()
+	};
+	let mut nodes = NodeState::new(&config, Alerts::default(), vec![]);
+
+	let now = Utc::now();
+	// older than the retention, so every tick purges them
+	let response = |uptime: f64, ago: i64| NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: now - chrono::Duration::seconds(ago),
+		data: json::json!({"statistics": {"uptime": uptime}}),
+	};
+
+	assert!(nodes.receive(response(1000.0, 120), now).is_empty());
+	assert!(nodes.tick(now).0);
+	assert!(nodes.snapshot(1).responses().is_empty());
+
+	let events = nodes.receive(response(10.0, 60), now);
+	assert!(matches!(events[..], [NodeEvent::Rebooted(_)]));
+	assert!(nodes.tick(now).0);
+	assert_eq!(nodes.snapshot(2).reboots()["c04a00dd692a"].count, 1);
+
+	// forgotten long after the buffer dropped it
+	nodes.tick(now + chrono::Duration::minutes(30));
+	assert_eq!(nodes.snapshot(3).reboots().len(), 1);
+	nodes.tick(now + chrono::Duration::hours(2));
+	assert!(nodes.snapshot(4).reboots().is_empty());
+}
+
+#[test]
+fn subscriber_drop_policies() {
+	let response = |nodeid: &str| Event::Response(NodeResponse {
+		nodeid: nodeid.to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: Utc::now(),
+		data: json::json!({}),
+	});
+	let nodeid = |event: Event| match event {
+		Event::Response(r) => r.nodeid,
+		e => panic!("unexpected event {:?}", e),
+	};
+	let queue = |policy| EventQueue { capacity: 2, policy };
+
+	let (subscriber, receiver) = Subscriber::new("oldest", queue(DropPolicy::DropOldest));
+	for id in ["a", "b", "c", "d"] {
+		subscriber.send(response(id));
+	}
+	assert_eq!(receiver.dropped(), 2);
+	assert_eq!(subscriber.stats().queued, 2);
+	assert_eq!(nodeid(receiver.try_recv().unwrap()), "c");
+	assert_eq!(nodeid(receiver.try_recv().unwrap()), "d");
+
+	let (subscriber, receiver) = Subscriber::new("newest", queue(DropPolicy::DropNewest));
+	for id in ["a", "b", "c", "d"] {
+		subscriber.send(response(id));
+	}
+	assert_eq!(receiver.dropped(), 2);
+	assert_eq!(nodeid(receiver.try_recv().unwrap()), "a");
+	assert_eq!(nodeid(receiver.try_recv().unwrap()), "b");
+
+	// waits for room instead of dropping
+	let (subscriber, receiver) = Subscriber::new("block", queue(DropPolicy::Block));
+	let sender = std::thread::spawn(move || {
+		for id in ["a", "b", "c", "d"] {
+			subscriber.send(response(id));
+		}
+		subscriber
+	});
+	for id in ["a", "b", "c", "d"] {
+		assert_eq!(nodeid(receiver.recv_timeout(Duration::from_secs(5)).unwrap()), id);
+	}
+	let subscriber = sender.join().unwrap();
+	assert_eq!(receiver.dropped(), 0);
+
+	// doesn't wait for a receiver that is gone
+	subscriber.send(response("e"));
+	subscriber.send(response("f"));
+	drop(receiver);
+	subscriber.send(response("g"));
+	assert!(!subscriber.is_alive());
+}
@@ -2,8 +2,9 @@
 use log::{debug, error, info, trace, warn};
 use serde;
 use serde::{Deserialize, Serialize};
+use serde_json as json;
 use serde_yaml as yaml;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{Read, Error as IoError};
 use std::path;
@@ -14,7 +15,9 @@ use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 pub enum ConfigLoadingError {
 	NoConfigFound,
 	Io(IoError),
-	Yaml(yaml::Error)
+	Yaml(yaml::Error),
+	/// parsed, but the settings don't work together
+	Invalid(String),
 }
 
 
@@ -39,6 +42,14 @@ pub struct Config {
 	pub web: Option<WebEndpoint>,
 	pub mqtt: Option<MqttEndpoint>,
 	pub zmq: Option<ZmqEndpoint>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub files: Option<FileEndpoint>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub alerts: Option<Alerts>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub alertmanager: Option<AlertmanagerEndpoint>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub inventory: Option<Inventory>,
 }
 
 impl Config {
@@ -49,9 +60,32 @@ impl Config {
 		File::open(path)?.read_to_string(&mut config_str)?;
 
 		let conf: Self = yaml::from_str(&config_str)?;
+		conf.validate().map_err(ConfigLoadingError::Invalid)?;
 
 		Ok(conf)
 	}
+
+	/// checks that serde can't do on a single field
+	pub fn validate(&self) -> Result<(), String> {
+		// the collector would wake up all the time
+		if self.requestd.publish_interval == 0 {
+			return Err("requestd.publish_interval has to be at least 1".to_string());
+		}
+
+		let redactions = [
+			("web", self.web.as_ref().and_then(|w| w.redact.as_ref())),
+			("mqtt", self.mqtt.as_ref().and_then(|m| m.redact.as_ref())),
+			("zmq", self.zmq.as_ref().and_then(|z| z.redact.as_ref())),
+			("files", self.files.as_ref().and_then(|f| f.redact.as_ref())),
+		];
+		for (endpoint, redaction) in redactions {
+			if let Some(redaction) = redaction {
+				redaction.validate().map_err(|e| format!("{}.redact: {}", endpoint, e))?;
+			}
+		}
+
+		Ok(())
+	}
 }
 
 fn get_first_file_found<'a>(files: &[&'a str]) -> Result<&'a str, ConfigLoadingError> {
@@ -71,6 +105,10 @@ impl Default for Config {
 			web: Some(WebEndpoint::default()),
 			mqtt: None,
 			zmq: Some(ZmqEndpoint::default()),
+			files: None,
+			alerts: None,
+			alertmanager: None,
+			inventory: None,
 		}
 	}
 }
@@ -85,6 +123,16 @@ pub struct Requestd {
 	pub categories: Vec<String>,
 	pub clean_interval: u64,
 	pub retention: u64,
+	/// nodes that didn't respond for this many seconds are considered offline
+	pub offline_after: u64,
+	/// minimum time in milliseconds between two snapshots of the buffer
+	/// served by the http endpoint
+	pub publish_interval: u64,
+	pub event_queue: EventQueue,
+	/// every response passes these processors in order before it is stored
+	pub processors: Vec<ProcessorConfig>,
+	/// number of reboots kept per node
+	pub reboot_history: usize,
 }
 
 impl Default for Requestd {
@@ -95,12 +143,117 @@ impl Default for Requestd {
 			// retention: 60*24*72, // retention of 3 days
 			retention: 10, // retention of 3 days
 			clean_interval: 10, // check for invalid responses every 2 minutes
+			offline_after: 180,
+			publish_interval: 1000,
 			multicast_address: "ff05::2:1001".to_string(),
 			categories: vec![
 				"nodeinfo".to_string(),
 				"statistics".to_string(),
 				"neighbours".to_string(),
 			],
+			event_queue: EventQueue::default(),
+			processors: vec![
+				ProcessorConfig::Validate { require: vec![] },
+				ProcessorConfig::Rates { window: 0 },
+			],
+			reboot_history: 10,
+		}
+	}
+}
+
+
+/// Configuration of a single processor. Paths are dotted json paths
+/// into the response data like `nodeinfo.owner.contact`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ProcessorConfig {
+	/// drop responses that don't look like respondd data
+	Validate {
+		/// paths that must be present
+		#[serde(default)]
+		require: Vec<String>,
+	},
+	/// add values that are missing in the response
+	Enrich {
+		set: BTreeMap<String, json::Value>,
+	},
+	/// only keep responses where the value at `path` equals one of `values`
+	Filter {
+		path: String,
+		/// match if the path exists at all if empty
+		#[serde(default)]
+		values: Vec<json::Value>,
+	},
+	/// drop responses where the value at `path` equals one of `values`
+	Drop {
+		path: String,
+		/// match if the path exists at all if empty
+		#[serde(default)]
+		values: Vec<json::Value>,
+	},
+	/// add our own data from a yaml or csv file under `key`
+	Metadata {
+		file: path::PathBuf,
+		#[serde(default = "default_metadata_key")]
+		key: String,
+	},
+	/// add per second rates of the traffic counters under `rates`
+	Rates {
+		/// seconds the previous counters of a node are kept. At least twice
+		/// `requestd.interval`
+		#[serde(default)]
+		window: u64,
+	},
+	/// remove, move and overwrite values
+	Rewrite {
+		#[serde(default)]
+		remove: Vec<String>,
+		#[serde(default)]
+		rename: BTreeMap<String, String>,
+		#[serde(default)]
+		set: BTreeMap<String, json::Value>,
+	},
+}
+
+fn default_metadata_key() -> String {
+	"meta".to_string()
+}
+
+
+/// what to do with new events when a subscriber's queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+	/// discard the oldest queued event to make room
+	DropOldest,
+	/// discard the event that does not fit anymore
+	DropNewest,
+	/// wait until the subscriber has made room. This stalls the collector!
+	Block,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventQueue {
+	#[serde(deserialize_with = "at_least_one")]
+	pub capacity: usize,
+	pub policy: DropPolicy,
+}
+
+fn at_least_one<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+	let n = usize::deserialize(deserializer)?;
+	if n < 1 {
+		return Err(serde::de::Error::invalid_value(serde::de::Unexpected::Unsigned(0), &"at least 1"));
+	}
+
+	Ok(n)
+}
+
+impl Default for EventQueue {
+	fn default() -> Self {
+		Self {
+			capacity: 1024,
+			policy: DropPolicy::DropOldest,
 		}
 	}
 }
@@ -114,24 +267,248 @@ pub struct Event {
 	pub vars: HashMap<String, String>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Alerts {
+	pub rules: Vec<AlertRule>,
+	/// every firing and resolved alert is POSTed as json to this url
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub webhook: Option<String>,
+	/// seconds between two checks of all known nodes
+	pub check_interval: u64,
+	/// Nodes that didn't respond for this many seconds are forgotten and
+	/// their alerts resolved. Longer than the `offline_seconds` thresholds
+	pub forget_after: u64,
+}
+
+impl Default for Alerts {
+	fn default() -> Self {
+		Self {
+			rules: vec![],
+			webhook: None,
+			check_interval: 30,
+			forget_after: default_forget_after(),
+		}
+	}
+}
+
+fn default_forget_after() -> u64 {
+	7 * 24 * 60 * 60
+}
+
+/// Compares a value of every response with `value`. Either `path` or
+/// `metric` must be set.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+	pub name: String,
+	/// dotted json path like `statistics.loadavg`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub path: Option<String>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub metric: Option<AlertMetric>,
+	pub op: AlertOp,
+	pub value: json::Value,
+	/// seconds the condition must hold before the alert fires
+	#[serde(default, rename = "for")]
+	pub duration: u64,
+	/// only check these nodes. All nodes if empty
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub nodes: Vec<String>,
+}
+
+/// values that are derived from the response
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+	/// used memory between 0 and 1
+	MemoryUsage,
+	/// seconds since the last response
+	OfflineSeconds,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AlertOp {
+	#[serde(rename = ">")]
+	Greater,
+	#[serde(rename = ">=")]
+	GreaterOrEqual,
+	#[serde(rename = "<")]
+	Less,
+	#[serde(rename = "<=")]
+	LessOrEqual,
+	#[serde(rename = "==")]
+	Equal,
+	#[serde(rename = "!=")]
+	NotEqual,
+}
+
+
+/// nodes that must always be up
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Inventory {
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub nodes: Vec<ExpectedNode>,
+	/// more nodes from a yaml list or a csv file with a `nodeid` column
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub file: Option<path::PathBuf>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedNode {
+	pub nodeid: String,
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub labels: BTreeMap<String, String>,
+}
+
+
+/// Personal data to remove before responses are published.
+/// Paths are dotted json paths like `nodeinfo.owner.contact`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Redaction {
+	pub drop: Vec<String>,
+	/// round `nodeinfo.location` to this many decimals
+	pub round_coordinates: Option<u32>,
+	/// replace every mac address with a hash
+	pub hash_macs: bool,
+	/// replace node ids with a hash
+	pub hash_nodeids: bool,
+	/// Required for `hash_macs` and `hash_nodeids`. Without a secret salt
+	/// the hashed macs can easily be brute forced
+	pub hash_salt: String,
+}
+
+impl Redaction {
+	fn validate(&self) -> Result<(), String> {
+		if (self.hash_macs || self.hash_nodeids) && self.hash_salt.is_empty() {
+			return Err("hash_salt is required to hash macs or node ids".to_string());
+		}
+
+		Ok(())
+	}
+}
+
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct WebEndpoint {
 	pub listen: SocketAddr,
+	/// applied to everything served on `listen`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub redact: Option<Redaction>,
+	/// serves the unredacted data. Don't expose this to the public!
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub internal_listen: Option<SocketAddr>,
+	/// nodes running an older release are listed as lagging in the rollout report
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub target_release: Option<String>,
+	/// number of events `/events` keeps for clients that reconnect
+	#[serde(default = "default_event_replay")]
+	pub event_replay: usize,
+	/// serves the websocket api at `/ws`. Plain http only
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub websocket_listen: Option<SocketAddr>,
+	/// serve https on `listen`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub tls: Option<Tls>,
+	/// bearer tokens
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub tokens: Vec<AccessToken>,
+	/// basic auth users
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub users: Vec<User>,
+	/// answer requests on `listen` without credentials with 401
+	/// instead of the public view
+	#[serde(default)]
+	pub require_auth: bool,
+	#[serde(default)]
+	pub map: MapConfig,
+}
+
+fn default_event_replay() -> usize {
+	100
 }
 
 impl Default for WebEndpoint {
 	fn default() -> Self {
 		Self {
-			listen: SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), 21001)
+			listen: SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), 21001),
+			redact: None,
+			internal_listen: None,
+			target_release: None,
+			event_replay: default_event_replay(),
+			websocket_listen: None,
+			tls: None,
+			tokens: vec![],
+			users: vec![],
+			require_auth: false,
+			map: MapConfig::default(),
 		}
 	}
 }
 
 
+/// background of `/map`
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct MapConfig {
+	/// like `https://tile.openstreetmap.org/{z}/{x}/{y}.png`. Without tiles a grid is drawn,
+	/// so the map works without internet access
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub tiles: Option<String>,
+	/// shown in the corner, most tile servers require it
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub attribution: Option<String>,
+}
+
+
+/// pem files, reloaded on SIGHUP
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Tls {
+	pub certificate: path::PathBuf,
+	pub key: path::PathBuf,
+}
+
+
+/// what an authenticated client may do. Every scope includes the ones before.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+	/// the redacted data
+	Public,
+	/// the unredacted data
+	Full,
+	/// actions like requesting a node
+	Admin,
+}
+
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AccessToken {
+	pub token: String,
+	pub scope: Scope,
+}
+
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct User {
+	pub name: String,
+	/// hex encoded, e.g. from `echo -n secret | sha256sum`
+	pub password_sha256: String,
+	pub scope: Scope,
+}
+
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct MqttEndpoint {
 	pub broker: String,
 	pub topic: String,
+	/// overrides `requestd.event_queue` for this endpoint
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub queue: Option<EventQueue>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub redact: Option<Redaction>,
 }
 
 impl Default for MqttEndpoint {
@@ -139,6 +516,8 @@ impl Default for MqttEndpoint {
 		Self {
 			broker: "localhost:1883".to_string(),
 			topic: "requestd/responses".to_string(),
+			queue: None,
+			redact: None,
 		}
 	}
 }
@@ -147,17 +526,84 @@ impl Default for MqttEndpoint {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ZmqEndpoint {
 	pub bind_to: String,
+	/// overrides `requestd.event_queue` for this endpoint
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub queue: Option<EventQueue>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub redact: Option<Redaction>,
 }
 
 impl Default for ZmqEndpoint {
 	fn default() -> Self {
 		Self {
 			bind_to: "tcp://*:21002".to_string(),
+			queue: None,
+			redact: None,
 		}
 	}
 }
 
 
+/// pushes alerts to a prometheus alertmanager
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertmanagerEndpoint {
+	/// base url like `http://localhost:9093`
+	pub url: String,
+	/// seconds between two pushes of the firing alerts
+	#[serde(default = "default_resend_interval")]
+	pub resend_interval: u64,
+	/// send an alert for every offline node
+	#[serde(default = "default_true")]
+	pub offline: bool,
+	/// offline nodes are forgotten after this many seconds without a response
+	#[serde(default = "default_forget_after")]
+	pub forget_after: u64,
+}
+
+fn default_resend_interval() -> u64 {
+	60
+}
+
+fn default_true() -> bool {
+	true
+}
+
+
+/// writes the collected data to files in regular intervals
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileEndpoint {
+	/// default number of seconds between two writes
+	pub interval: u64,
+	pub outputs: Vec<FileOutput>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub redact: Option<Redaction>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileOutput {
+	pub format: FileFormat,
+	pub path: path::PathBuf,
+	/// overrides the default interval
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub interval: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+	Meshviewer,
+	/// nodes.json version 1
+	NodesV1,
+	/// nodes.json version 2
+	NodesV2,
+	/// graph.json in batadv-vis format
+	Graph,
+}
+
+
 
 #[test]
 fn loading_nonexisting_config() {
@@ -166,3 +612,35 @@ fn loading_nonexisting_config() {
 		_ => panic!()
 	}
 }
+
+#[test]
+fn event_queue_needs_room() {
+	assert!(yaml::from_str::<EventQueue>("capacity: 0").is_err());
+	assert_eq!(yaml::from_str::<EventQueue>("capacity: 1").unwrap().capacity, 1);
+}
+
+#[test]
+fn hashing_needs_a_salt() {
+	let mut redaction = Redaction {
+		hash_macs: true,
+		..Default::default()
+	};
+	assert!(redaction.validate().is_err());
+	redaction.hash_salt = "some secret".to_string();
+	assert!(redaction.validate().is_ok());
+
+	let mut config = Config::default();
+	config.web.as_mut().unwrap().redact = Some(Redaction {
+		hash_nodeids: true,
+		..Default::default()
+	});
+	assert!(config.validate().unwrap_err().starts_with("web.redact"));
+}
+
+#[test]
+fn publish_interval_is_not_zero() {
+	let mut config = Config::default();
+	assert!(config.validate().is_ok());
+	config.requestd.publish_interval = 0;
+	assert!(config.validate().unwrap_err().starts_with("requestd.publish_interval"));
+}
@@ -0,0 +1,113 @@
+//! content negotiation and compression of http responses
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use std::io::Write;
+
+/// brotli is slow at higher levels and the bodies are compressed once per snapshot
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+	Identity,
+	Gzip,
+	Deflate,
+	Brotli,
+}
+
+impl Encoding {
+	/// the best encoding the client accepts.
+	///
+	/// `Accept-Encoding: gzip;q=0.8, br` picks brotli. On equal weights
+	/// brotli is preferred over gzip over deflate.
+	pub fn negotiate(accept: &str) -> Self {
+		let mut best = (0.0, Self::Identity);
+
+		for part in accept.split(',') {
+			let mut params = part.split(';').map(|p| p.trim());
+			let encoding = match params.next() {
+				Some(e) if e.eq_ignore_ascii_case("br") => Self::Brotli,
+				Some(e) if e.eq_ignore_ascii_case("gzip") || e.eq_ignore_ascii_case("x-gzip") => Self::Gzip,
+				Some(e) if e.eq_ignore_ascii_case("deflate") => Self::Deflate,
+				_ => continue,
+			};
+			let q = params
+				.find_map(|p| p.strip_prefix("q="))
+				.and_then(|q| q.parse::<f32>().ok())
+				.unwrap_or(1.0);
+
+			if q > best.0 || (q == best.0 && q > 0.0 && encoding.rank() > best.1.rank()) {
+				best = (q, encoding);
+			}
+		}
+
+		best.1
+	}
+
+	fn rank(&self) -> u8 {
+		match self {
+			Self::Identity => 0,
+			Self::Deflate => 1,
+			Self::Gzip => 2,
+			Self::Brotli => 3,
+		}
+	}
+
+	/// value of the `Content-Encoding` header
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Identity => "identity",
+			Self::Gzip => "gzip",
+			Self::Deflate => "deflate",
+			Self::Brotli => "br",
+		}
+	}
+
+	pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+		match self {
+			Self::Identity => data.to_vec(),
+			Self::Gzip => {
+				let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+				encoder.write_all(data).unwrap();
+				encoder.finish().unwrap()
+			}
+			Self::Deflate => {
+				let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+				encoder.write_all(data).unwrap();
+				encoder.finish().unwrap()
+			}
+			Self::Brotli => {
+				let mut out = Vec::new();
+				{
+					let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+					encoder.write_all(data).unwrap();
+				}
+				out
+			}
+		}
+	}
+}
+
+
+
+#[test]
+fn negotiate_and_encode() {
+	use std::io::Read;
+
+	assert_eq!(Encoding::negotiate(""), Encoding::Identity);
+	assert_eq!(Encoding::negotiate("gzip, deflate, br"), Encoding::Brotli);
+	assert_eq!(Encoding::negotiate("br;q=0.5, gzip"), Encoding::Gzip);
+	assert_eq!(Encoding::negotiate("br;q=0, deflate"), Encoding::Deflate);
+	assert_eq!(Encoding::negotiate("compress, *"), Encoding::Identity);
+
+	let data = br#"[{"nodeid": "c04a00dd692a"}, {"nodeid": "60e32710f3ec"}]"#.repeat(100);
+
+	let mut decoded = vec![];
+	flate2::read::GzDecoder::new(&Encoding::Gzip.encode(&data)[..]).read_to_end(&mut decoded).unwrap();
+	assert_eq!(decoded, data);
+
+	let mut decoded = vec![];
+	brotli::Decompressor::new(&Encoding::Brotli.encode(&data)[..], 4096).read_to_end(&mut decoded).unwrap();
+	assert_eq!(decoded, data);
+}
@@ -0,0 +1,69 @@
+//! everything that is sent to the subscribers of the collector
+use crate::alerts::Alert;
+use crate::inventory::InventoryChange;
+use crate::reboots::Reboot;
+use crate::NodeResponse;
+use serde::Serialize;
+
+
+/// Responses are serialized unchanged, so existing consumers keep working.
+/// Everything else carries an `event` field with its kind.
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum Event {
+	Response(NodeResponse),
+	Node(NodeEvent),
+}
+
+/// something happened to a node
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum NodeEvent {
+	Rebooted(Reboot),
+	Alert(Alert),
+	/// the status of a node from the inventory changed
+	Inventory(InventoryChange),
+}
+
+impl Event {
+	/// `response` or the name of the node event
+	pub fn kind(&self) -> &'static str {
+		match self {
+			Self::Response(_) => "response",
+			Self::Node(NodeEvent::Rebooted(_)) => "rebooted",
+			Self::Node(NodeEvent::Alert(_)) => "alert",
+			Self::Node(NodeEvent::Inventory(_)) => "inventory",
+		}
+	}
+
+	pub fn nodeid(&self) -> &str {
+		match self {
+			Self::Response(r) => &r.nodeid,
+			Self::Node(NodeEvent::Rebooted(r)) => &r.nodeid,
+			Self::Node(NodeEvent::Alert(a)) => &a.nodeid,
+			Self::Node(NodeEvent::Inventory(i)) => &i.nodeid,
+		}
+	}
+}
+
+impl NodeEvent {
+	pub fn nodeid_mut(&mut self) -> &mut String {
+		match self {
+			Self::Rebooted(r) => &mut r.nodeid,
+			Self::Alert(a) => &mut a.nodeid,
+			Self::Inventory(i) => &mut i.nodeid,
+		}
+	}
+}
+
+impl From<NodeResponse> for Event {
+	fn from(response: NodeResponse) -> Self {
+		Self::Response(response)
+	}
+}
+
+impl From<NodeEvent> for Event {
+	fn from(event: NodeEvent) -> Self {
+		Self::Node(event)
+	}
+}
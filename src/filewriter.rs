@@ -0,0 +1,178 @@
+//! Writes meshviewer.json, nodes.json and graph.json to files for web servers
+//! and maps that can't talk to the http endpoint.
+use crate::collector::{CollectorHandle, Snapshot};
+use crate::config::{FileFormat, FileOutput};
+use crate::legacy;
+use crate::meshviewer;
+use crate::privacy::Redactor;
+use crate::Endpoint;
+use crate::CONFIG;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// how often the schedule is checked
+const TICK: Duration = Duration::from_secs(1);
+
+
+/// writes the configured formats to files in regular intervals
+pub struct FileWriter {
+	collector: CollectorHandle,
+	redactor: Redactor,
+	outputs: Vec<ScheduledOutput>,
+}
+
+struct ScheduledOutput {
+	output: FileOutput,
+	interval: Duration,
+	next: Instant,
+	/// generation of the last written snapshot
+	generation: Option<u64>,
+}
+
+
+impl FileFormat {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Meshviewer => "meshviewer",
+			Self::NodesV1 => "nodes_v1",
+			Self::NodesV2 => "nodes_v2",
+			Self::Graph => "graph",
+		}
+	}
+
+	pub fn render(&self, snapshot: &Snapshot) -> Vec<u8> {
+		match self {
+			Self::Meshviewer => meshviewer::render(snapshot),
+			Self::NodesV1 => legacy::render_nodes_v1(snapshot),
+			Self::NodesV2 => legacy::render_nodes_v2(snapshot),
+			Self::Graph => legacy::render_graph(snapshot),
+		}
+	}
+}
+
+
+impl ScheduledOutput {
+	/// Due and the snapshot changed since the last write. The empty snapshot
+	/// before the first responses would replace a good file of the last run.
+	fn is_due(&self, generation: u64, now: Instant) -> bool {
+		generation > 0 && self.next <= now && self.generation != Some(generation)
+	}
+
+	fn write(&mut self, snapshot: &Snapshot, now: Instant) {
+		let output = &self.output;
+		trace!("writing {} to {}", output.format.name(), output.path.display());
+		let data = snapshot.rendered(output.format.name(), |s| output.format.render(s));
+		if let Err(e) = write_atomic(&output.path, &data) {
+			error!("can't write {}: {}", output.path.display(), e);
+		}
+
+		self.generation = Some(snapshot.generation());
+		self.next = now + self.interval;
+	}
+}
+
+
+/// write to a temporary file first, so readers never see a partial file
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+	let mut tmp = path.as_os_str().to_owned();
+	tmp.push(".tmp");
+
+	fs::write(&tmp, data)?;
+	fs::rename(&tmp, path)
+}
+
+
+impl Endpoint for FileWriter {
+	fn new(c: CollectorHandle) -> Self {
+		let conf = CONFIG.files.clone().unwrap();
+		let interval = conf.interval;
+
+		let outputs = conf
+			.outputs
+			.into_iter()
+			.map(|output| ScheduledOutput {
+				interval: Duration::from_secs(output.interval.unwrap_or(interval)),
+				next: Instant::now(),
+				generation: None,
+				output,
+			})
+			.collect();
+
+		Self {
+			collector: c,
+			redactor: Redactor::new(conf.redact),
+			outputs,
+		}
+	}
+
+	fn start(mut self) -> ! {
+		loop {
+			let snapshot = self.collector.snapshot();
+			let redactor = &self.redactor;
+			let mut redacted = None;
+
+			for scheduled in self.outputs.iter_mut() {
+				let now = Instant::now();
+				if !scheduled.is_due(snapshot.generation(), now) {
+					continue;
+				}
+
+				let snapshot = redacted.get_or_insert_with(|| {
+					if redactor.is_noop() {
+						snapshot.clone()
+					} else {
+						Arc::new(snapshot.redacted(redactor))
+					}
+				});
+
+				scheduled.write(snapshot, now);
+			}
+
+			thread::sleep(TICK);
+		}
+	}
+}
+
+
+
+#[test]
+fn writes_changed_snapshots_when_due() {
+	let path = std::env::temp_dir().join(format!("requestd-filewriter-{}.json", std::process::id()));
+	let start = Instant::now();
+	let mut scheduled = ScheduledOutput {
+		output: FileOutput {
+			format: FileFormat::NodesV2,
+			path: path.clone(),
+			interval: None,
+		},
+		interval: Duration::from_secs(60),
+		next: start,
+		generation: None,
+	};
+	let snapshot = |generation: u64| {
+		let now = chrono::Utc::now();
+		Snapshot::new(generation, now, vec![], 180, Default::default(), Default::default(), vec![], vec![])
+	};
+
+	// nothing collected yet
+	assert!(!scheduled.is_due(0, start));
+
+	assert!(scheduled.is_due(1, start));
+	scheduled.write(&snapshot(1), start);
+	let written: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+	assert_eq!(written["version"], 2);
+
+	// unchanged, or changed but the interval isn't over yet
+	let later = start + Duration::from_secs(61);
+	assert!(!scheduled.is_due(1, later));
+	assert!(!scheduled.is_due(2, start + Duration::from_secs(30)));
+	assert!(scheduled.is_due(2, later));
+
+	fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,288 @@
+//! nodes that are expected to be up, independent of the response buffer
+use crate::config::{ExpectedNode, Inventory};
+use crate::topology::escape_xml;
+use crate::{NodeId, NodeResponse, Timestamp};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+use serde_yaml as yaml;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Write};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+
+#[derive(Debug)]
+pub enum InventoryError {
+	Io(io::Error),
+	Yaml(yaml::Error),
+	Csv(csv::Error),
+	/// the csv file has no `nodeid` column
+	NoNodeId,
+}
+
+impl From<io::Error> for InventoryError {
+	fn from(e: io::Error) -> Self {
+		Self::Io(e)
+	}
+}
+
+impl From<yaml::Error> for InventoryError {
+	fn from(e: yaml::Error) -> Self {
+		Self::Yaml(e)
+	}
+}
+
+impl From<csv::Error> for InventoryError {
+	fn from(e: csv::Error) -> Self {
+		Self::Csv(e)
+	}
+}
+
+impl Display for InventoryError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Io(e) => write!(f, "{}", e),
+			Self::Yaml(e) => write!(f, "{}", e),
+			Self::Csv(e) => write!(f, "{}", e),
+			Self::NoNodeId => write!(f, "no nodeid column"),
+		}
+	}
+}
+
+
+/// the configured nodes and the nodes from the inventory file
+pub fn load(config: &Inventory) -> Result<Vec<ExpectedNode>, InventoryError> {
+	let mut nodes = config.nodes.clone();
+
+	if let Some(path) = &config.file {
+		let mut content = String::new();
+		File::open(path)?.read_to_string(&mut content)?;
+
+		let from_file = if is_csv(path) {
+			parse_csv(content.as_bytes())?
+		} else {
+			yaml::from_str(&content)?
+		};
+		info!("loaded {} expected nodes from {}", from_file.len(), path.display());
+		nodes.extend(from_file);
+	}
+
+	Ok(nodes)
+}
+
+fn is_csv(path: &Path) -> bool {
+	path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"))
+}
+
+/// every column besides `nodeid` is a label
+fn parse_csv<R: Read>(reader: R) -> Result<Vec<ExpectedNode>, InventoryError> {
+	let mut reader = csv::Reader::from_reader(reader);
+	let mut nodes = vec![];
+
+	for row in reader.deserialize() {
+		let mut labels: BTreeMap<String, String> = row?;
+		let nodeid = labels.remove("nodeid").ok_or(InventoryError::NoNodeId)?;
+		labels.retain(|_, v| !v.is_empty());
+
+		nodes.push(ExpectedNode {
+			nodeid,
+			labels,
+		});
+	}
+
+	Ok(nodes)
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+	Online,
+	/// didn't respond for `offline_after` seconds
+	Offline,
+	/// never responded since requestd started
+	NeverSeen,
+}
+
+impl Status {
+	pub const ALL: [Self; 3] = [Self::Online, Self::Offline, Self::NeverSeen];
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Online => "online",
+			Self::Offline => "offline",
+			Self::NeverSeen => "never_seen",
+		}
+	}
+}
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InventoryNode {
+	pub nodeid: NodeId,
+	pub labels: BTreeMap<String, String>,
+	pub hostname: Option<String>,
+	pub status: Status,
+	pub last_seen: Option<Timestamp>,
+}
+
+
+/// the status of an expected node changed
+#[derive(Clone, Debug, Serialize)]
+pub struct InventoryChange {
+	pub nodeid: NodeId,
+	pub labels: BTreeMap<String, String>,
+	pub hostname: Option<String>,
+	pub status: Status,
+	pub previous: Status,
+	pub last_seen: Option<Timestamp>,
+}
+
+
+/// Remembers when every expected node was last seen. Unlike the response
+/// buffer it never forgets a node.
+pub struct InventoryTracker {
+	nodes: Vec<InventoryNode>,
+	index: HashMap<NodeId, usize>,
+	/// seconds
+	offline_after: u64,
+}
+
+impl InventoryTracker {
+	pub fn new(expected: Vec<ExpectedNode>, offline_after: u64) -> Self {
+		let mut tracker = Self {
+			nodes: vec![],
+			index: HashMap::new(),
+			offline_after,
+		};
+
+		for node in expected {
+			if tracker.index.contains_key(&node.nodeid) {
+				warn!("{} is listed twice in the inventory", node.nodeid);
+				continue;
+			}
+
+			tracker.index.insert(node.nodeid.clone(), tracker.nodes.len());
+			tracker.nodes.push(InventoryNode {
+				nodeid: node.nodeid,
+				labels: node.labels,
+				hostname: None,
+				status: Status::NeverSeen,
+				last_seen: None,
+			});
+		}
+
+		tracker
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.nodes.is_empty()
+	}
+
+	pub fn seen(&mut self, response: &NodeResponse) {
+		if let Some(&i) = self.index.get(&response.nodeid) {
+			let node = &mut self.nodes[i];
+			node.last_seen = Some(response.timestamp);
+			if let Some(hostname) = response.data.pointer("/nodeinfo/hostname").and_then(|h| h.as_str()) {
+				node.hostname = Some(hostname.to_string());
+			}
+		}
+	}
+
+	/// recalculate the status of all nodes and return the ones that changed
+	pub fn update(&mut self, now: Timestamp) -> Vec<InventoryChange> {
+		let mut changes = vec![];
+
+		let offline_after = self.offline_after as i64;
+
+		for node in self.nodes.iter_mut() {
+			let status = match node.last_seen {
+				None => Status::NeverSeen,
+				Some(t) if (now - t).num_seconds() > offline_after => Status::Offline,
+				Some(_) => Status::Online,
+			};
+
+			if status != node.status {
+				debug!("expected node {} is {} now", node.nodeid, status.as_str());
+				changes.push(InventoryChange {
+					nodeid: node.nodeid.clone(),
+					labels: node.labels.clone(),
+					hostname: node.hostname.clone(),
+					status,
+					previous: node.status,
+					last_seen: node.last_seen,
+				});
+				node.status = status;
+			}
+		}
+
+		changes
+	}
+
+	pub fn report(&self) -> Vec<InventoryNode> {
+		self.nodes.clone()
+	}
+}
+
+
+pub fn to_html(nodes: &[InventoryNode]) -> Vec<u8> {
+	let mut out = String::from(concat!(
+		"<html>\n<head>\n<title>requestd inventory</title>\n",
+		"<style>table { border-collapse: collapse; } ",
+		"td, th { border: 1px solid #ccc; padding: 2px 8px; text-align: left; }</style>\n",
+		"</head>\n<body>\n<h1>Inventory</h1>\n",
+	));
+
+	let up = nodes.iter().filter(|n| n.status == Status::Online).count();
+	writeln!(out, "<p>{} of {} expected nodes are online</p>", up, nodes.len()).unwrap();
+
+	out.push_str("<table>\n<tr><th>node</th><th>hostname</th><th>status</th><th>last seen</th><th>labels</th></tr>\n");
+	for node in nodes {
+		let labels: Vec<String> = node.labels.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+		writeln!(
+			out,
+			"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+			escape_xml(&node.nodeid),
+			escape_xml(node.hostname.as_deref().unwrap_or("")),
+			node.status.as_str(),
+			node.last_seen.map_or(String::from("never"), |t| t.to_rfc3339()),
+			escape_xml(&labels.join(", ")),
+		)
+		.unwrap();
+	}
+
+	out.push_str("</table>\n</body>\n</html>\n");
+	out.into_bytes()
+}
+
+
+
+#[test]
+fn inventory_status_changes() {
+	let csv = "nodeid,role,site\nc04a00dd692a,backbone,ffhl\n60e32710f3ec,,\n";
+	let expected = parse_csv(csv.as_bytes()).unwrap();
+	assert_eq!(expected[0].labels["role"], "backbone");
+	assert!(expected[1].labels.is_empty());
+
+	let mut tracker = InventoryTracker::new(expected, 180);
+	let now = chrono::Utc::now();
+	assert!(tracker.update(now).is_empty());
+
+	tracker.seen(&NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: now,
+		data: serde_json::json!({}),
+	});
+	let changes = tracker.update(now);
+	assert_eq!(changes.len(), 1);
+	assert_eq!(changes[0].status, Status::Online);
+	assert_eq!(changes[0].previous, Status::NeverSeen);
+
+	assert!(tracker.update(now + chrono::Duration::seconds(180)).is_empty());
+	let changes = tracker.update(now + chrono::Duration::seconds(181));
+	assert_eq!(changes[0].status, Status::Offline);
+	assert_eq!(changes[0].previous, Status::Online);
+}
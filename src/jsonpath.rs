@@ -0,0 +1,128 @@
+//! simple dotted paths into json values like `nodeinfo.owner.contact`
+//!
+//! Array elements are addressed by their index. `*` matches every element
+//! of an object or array.
+use serde_json as json;
+use serde_json::Value;
+
+pub const WILDCARD: &str = "*";
+
+
+fn segments(path: &str) -> Vec<&str> {
+	path.split('.').filter(|s| !s.is_empty()).collect()
+}
+
+fn child<'a>(value: &'a Value, segment: &str) -> Option<&'a Value> {
+	match value {
+		Value::Object(map) => map.get(segment),
+		Value::Array(list) => segment.parse::<usize>().ok().and_then(|i| list.get(i)),
+		_ => None,
+	}
+}
+
+fn child_mut<'a>(value: &'a mut Value, segment: &str) -> Option<&'a mut Value> {
+	match value {
+		Value::Object(map) => map.get_mut(segment),
+		Value::Array(list) => segment.parse::<usize>().ok().and_then(move |i| list.get_mut(i)),
+		_ => None,
+	}
+}
+
+
+/// get the value at `path`. Wildcards are not supported here
+pub fn get<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+	segments(path).iter().try_fold(value, |v, s| child(v, s))
+}
+
+/// call `f` for every value matching `path`
+pub fn for_each_mut<F>(value: &mut Value, path: &str, f: &mut F)
+where
+	F: FnMut(&mut Value),
+{
+	walk_mut(value, &segments(path), f);
+}
+
+fn walk_mut<F>(value: &mut Value, segments: &[&str], f: &mut F)
+where
+	F: FnMut(&mut Value),
+{
+	let (first, rest) = match segments.split_first() {
+		Some(s) => s,
+		None => return f(value),
+	};
+
+	if *first == WILDCARD {
+		match value {
+			Value::Object(map) => map.values_mut().for_each(|v| walk_mut(v, rest, f)),
+			Value::Array(list) => list.iter_mut().for_each(|v| walk_mut(v, rest, f)),
+			_ => (),
+		}
+	} else if let Some(v) = child_mut(value, first) {
+		walk_mut(v, rest, f);
+	}
+}
+
+/// remove all values matching `path` and return how many were removed
+pub fn remove(value: &mut Value, path: &str) -> usize {
+	let segments = segments(path);
+	let (last, parent) = match segments.split_last() {
+		Some(s) => s,
+		None => return 0,
+	};
+
+	let mut removed = 0;
+	walk_mut(value, parent, &mut |v| match v {
+		Value::Object(map) if *last == WILDCARD => {
+			removed += map.len();
+			map.clear();
+		}
+		Value::Object(map) => {
+			removed += map.remove(*last).is_some() as usize;
+		}
+		Value::Array(list) if *last == WILDCARD => {
+			removed += list.len();
+			list.clear();
+		}
+		_ => (),
+	});
+
+	removed
+}
+
+/// set the value at `path`. Missing objects on the way are created
+pub fn set(value: &mut Value, path: &str, new: Value) {
+	let mut current = value;
+
+	for segment in segments(path) {
+		if !current.is_object() && !current.is_array() {
+			*current = Value::Object(json::Map::new());
+		}
+
+		current = match current {
+			Value::Object(map) => map.entry(segment).or_insert(Value::Null),
+			Value::Array(list) => match segment.parse::<usize>() {
+				Ok(i) if i < list.len() => &mut list[i],
+				_ => return,
+			},
+			_ => unreachable!(),
+		};
+	}
+
+	*current = new;
+}
+
+
+
+#[test]
+fn get_set_and_remove() {
+	let mut v = json::json!({"nodeinfo": {"owner": {"contact": "me@example.org"}, "network": {"addresses": ["a", "b"]}}});
+
+	assert_eq!(get(&v, "nodeinfo.network.addresses.1"), Some(&json::json!("b")));
+	assert_eq!(get(&v, "nodeinfo.location.latitude"), None);
+
+	set(&mut v, "nodeinfo.location.latitude", json::json!(53.8));
+	assert_eq!(get(&v, "nodeinfo.location.latitude"), Some(&json::json!(53.8)));
+
+	assert_eq!(remove(&mut v, "nodeinfo.*.contact"), 1);
+	assert_eq!(get(&v, "nodeinfo.owner"), Some(&json::json!({})));
+}
@@ -0,0 +1,233 @@
+//! nodes.json (version 1 and 2) and graph.json as used by
+//! ffmap-d3, hopglass and the freifunk api
+use crate::collector::Snapshot;
+use crate::mesh::{LinkType, MacIndex};
+use crate::meshviewer::format_time;
+use crate::rates::RATES_KEY;
+use crate::NodeResponse;
+use serde_json as json;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// graph.json stores the inverse tq. This is used for links without tq.
+const MAX_INVERSE_TQ: f64 = 1000.0;
+
+/// statistics that are passed through unchanged
+const STATISTICS: &[&str] = &[
+	"uptime", "idletime", "loadavg", "rootfs_usage", "processes", "traffic", "mesh_vpn", "wireless",
+];
+
+
+/// nodes.json version 1: nodes are a map from node id to node
+pub fn render_nodes_v1(snapshot: &Snapshot) -> Vec<u8> {
+	let index = &snapshot.topology().index;
+	let nodes: json::Map<String, Value> = snapshot
+		.responses()
+		.iter()
+		.map(|r| (r.nodeid.clone(), node(r, snapshot, index)))
+		.collect();
+
+	json::to_vec(&json::json!({
+		"version": 1,
+		"timestamp": format_time(snapshot.created()),
+		"nodes": nodes,
+	}))
+	.unwrap()
+}
+
+/// nodes.json version 2: nodes are a list
+pub fn render_nodes_v2(snapshot: &Snapshot) -> Vec<u8> {
+	let index = &snapshot.topology().index;
+	let nodes: Vec<Value> = snapshot.responses().iter().map(|r| node(r, snapshot, index)).collect();
+
+	json::to_vec(&json::json!({
+		"version": 2,
+		"timestamp": format_time(snapshot.created()),
+		"nodes": nodes,
+	}))
+	.unwrap()
+}
+
+fn node(response: &NodeResponse, snapshot: &Snapshot, index: &MacIndex) -> Value {
+	let data = &response.data;
+	let number = |pointer: &str| data.pointer(pointer).and_then(|v| v.as_f64());
+
+	let mut statistics = json::Map::new();
+	statistics.insert("node_id".to_string(), json::json!(response.nodeid));
+	statistics.insert("clients".to_string(), json::json!(number("/statistics/clients/total").unwrap_or(0.0)));
+
+	for key in STATISTICS {
+		if let Some(v) = data.pointer(&format!("/statistics/{}", key)) {
+			statistics.insert(key.to_string(), v.clone());
+		}
+	}
+
+	if let Some(usage) = memory_usage(data) {
+		statistics.insert("memory_usage".to_string(), json::json!(usage));
+	}
+
+	if let Some(rates) = data.get(RATES_KEY) {
+		statistics.insert(RATES_KEY.to_string(), rates.clone());
+	}
+
+	for key in &["gateway", "gateway6", "gateway_nexthop"] {
+		let gateway = data
+			.pointer(&format!("/statistics/{}", key))
+			.and_then(|g| g.as_str())
+			.and_then(|g| index.nodeid(g));
+		if let Some(nodeid) = gateway {
+			statistics.insert(key.to_string(), json::json!(nodeid));
+		}
+	}
+
+	json::json!({
+		"firstseen": format_time(snapshot.first_seen(&response.nodeid).unwrap_or(response.timestamp)),
+		"lastseen": format_time(response.timestamp),
+		"flags": {
+			"online": snapshot.is_online(response),
+			"gateway": data.pointer("/nodeinfo/vpn").and_then(|v| v.as_bool()).unwrap_or(false),
+		},
+		"nodeinfo": data.get("nodeinfo").cloned().unwrap_or_else(|| json::json!({"node_id": response.nodeid})),
+		"statistics": statistics,
+	})
+}
+
+/// Used memory between 0 and 1. Uses `available` if the node reports it,
+/// otherwise buffers and caches count as free.
+pub fn memory_usage(data: &Value) -> Option<f64> {
+	let number = |pointer: &str| data.pointer(pointer).and_then(|v| v.as_f64());
+
+	let total = number("/statistics/memory/total")?;
+	if total <= 0.0 {
+		return None;
+	}
+
+	// newer kernels report what is actually available to programs
+	if let Some(available) = number("/statistics/memory/available") {
+		return Some(1.0 - available / total);
+	}
+
+	let free = number("/statistics/memory/free")?
+		+ number("/statistics/memory/buffers").unwrap_or(0.0)
+		+ number("/statistics/memory/cached").unwrap_or(0.0);
+
+	Some(1.0 - free / total)
+}
+
+
+/// graph.json in the format of batadv-vis
+pub fn render_graph(snapshot: &Snapshot) -> Vec<u8> {
+	let responses = snapshot.responses();
+
+	let mut positions: HashMap<&str, usize> = HashMap::new();
+	let mut nodes = vec![];
+	for response in responses {
+		positions.insert(&response.nodeid, nodes.len());
+		nodes.push(json::json!({
+			"id": response.data.pointer("/nodeinfo/network/mac").cloned().unwrap_or(Value::Null),
+			"node_id": response.nodeid,
+		}));
+	}
+
+	let links: Vec<Value> = snapshot
+		.topology()
+		.links
+		.iter()
+		.filter_map(|l| {
+			let source = positions.get(l.source.as_str())?;
+			let target = positions.get(l.target.as_str())?;
+			let tqs: Vec<f64> = l.source_tq.iter().chain(l.target_tq.iter()).copied().collect();
+			let tq = tqs.iter().sum::<f64>() / tqs.len() as f64;
+
+			Some(json::json!({
+				"source": source,
+				"target": target,
+				"vpn": l.kind == LinkType::Vpn,
+				"tq": if tq > 0.0 { (1.0 / tq).min(MAX_INVERSE_TQ) } else { MAX_INVERSE_TQ },
+				"bidirect": l.source_tq.is_some() && l.target_tq.is_some(),
+			}))
+		})
+		.collect();
+
+	json::to_vec(&json::json!({
+		"version": 1,
+		"batadv": {
+			"directed": false,
+			"multigraph": false,
+			"graph": [],
+			"nodes": nodes,
+			"links": links,
+		},
+	}))
+	.unwrap()
+}
+
+
+
+#[test]
+fn nodes_and_graph() {
+	let node = |nodeid: &str, mac: &str, neighbours: Value, gateway: &str| {
+		std::sync::Arc::new(NodeResponse {
+			nodeid: nodeid.to_string(),
+			remote: "fe80::1".parse().unwrap(),
+			timestamp: chrono::Utc::now(),
+			data: json::json!({
+				"nodeinfo": {
+					"node_id": nodeid,
+					"network": {"mac": mac, "mesh": {"bat0": {"interfaces": {"wireless": [mac]}}}},
+				},
+				"statistics": {"gateway": gateway, "memory": {"total": 100, "free": 20, "buffers": 10, "cached": 10}},
+				"neighbours": {"batadv": {mac: {"neighbours": neighbours}}},
+			}),
+		})
+	};
+	let responses = vec![
+		node("aa", "02:00:00:00:00:aa", json::json!({"02:00:00:00:00:bb": {"tq": 255}}), "02:00:00:00:00:bb"),
+		// the neighbour 02:00:00:00:00:cc isn't known, so there is no link
+		node(
+			"bb",
+			"02:00:00:00:00:bb",
+			json::json!({"02:00:00:00:00:aa": {"tq": 255}, "02:00:00:00:00:cc": {"tq": 255}}),
+			"02:00:00:00:00:cc",
+		),
+	];
+
+	let snapshot =
+		Snapshot::new(1, chrono::Utc::now(), responses, 180, Default::default(), Default::default(), vec![], vec![]);
+
+	let v1: Value = json::from_slice(&render_nodes_v1(&snapshot)).unwrap();
+	let v2: Value = json::from_slice(&render_nodes_v2(&snapshot)).unwrap();
+
+	assert_eq!(v1["version"], 1);
+	assert_eq!(v2["version"], 2);
+
+	// the same nodes, keyed by node id in v1 and as a list in v2
+	let nodes = v1["nodes"].as_object().unwrap();
+	assert_eq!(nodes.keys().collect::<Vec<_>>(), ["aa", "bb"]);
+	assert_eq!(v2["nodes"].as_array().unwrap(), &nodes.values().cloned().collect::<Vec<_>>());
+
+	let aa = &nodes["aa"];
+	assert_eq!(aa["flags"], json::json!({"online": true, "gateway": false}));
+	assert_eq!(aa["nodeinfo"]["node_id"], "aa");
+	assert_eq!(aa["statistics"]["node_id"], "aa");
+	assert_eq!(aa["statistics"]["memory_usage"], 0.6);
+	// gateways are resolved from their mac to the node id, unknown ones are left out
+	assert_eq!(aa["statistics"]["gateway"], "bb");
+	assert!(nodes["bb"]["statistics"].get("gateway").is_none());
+
+	// links point to the position of the nodes, resolved by their macs
+	let graph: Value = json::from_slice(&render_graph(&snapshot)).unwrap();
+	let batadv = &graph["batadv"];
+
+	assert_eq!(
+		batadv["nodes"],
+		json::json!([
+			{"id": "02:00:00:00:00:aa", "node_id": "aa"},
+			{"id": "02:00:00:00:00:bb", "node_id": "bb"},
+		])
+	);
+	assert_eq!(
+		batadv["links"],
+		json::json!([{"source": 0, "target": 1, "vpn": false, "tq": 1.0, "bidirect": true}])
+	);
+}
@@ -1,14 +1,39 @@
+pub mod alertmanager;
+pub mod alerts;
+pub mod api;
+pub mod auth;
 pub mod collector;
 pub mod config;
+pub mod encoding;
+pub mod events;
+pub mod filewriter;
+pub mod inventory;
+pub mod jsonpath;
+pub mod legacy;
+pub mod map;
+pub mod mesh;
+pub mod meshviewer;
+pub mod metadata;
+pub mod metrics;
 pub mod mqtt;
 pub mod multicast;
+pub mod privacy;
+pub mod processor;
+pub mod query;
+pub mod rates;
+pub mod reboots;
+pub mod rollout;
+pub mod sse;
+pub mod topology;
 pub mod web;
+pub mod websocket;
 pub mod zmq;
 
 use chrono::{DateTime, Utc};
 use clap;
-use collector::Collector;
+use collector::{Collector, CollectorHandle};
 use config::Config;
+use crossbeam::channel::RecvTimeoutError;
 use lazy_static::lazy_static;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
@@ -20,11 +45,10 @@ use std::net::IpAddr;
 use std::process;
 use std::thread;
 use std::time::Duration;
-use std::sync::{Mutex, Arc};
 use config::ConfigLoadingError;
+use multicast::ResponddResponse;
 
 pub const DEFAULT_CONF_FILES: &[&str] = &["requestd.yml", "/etc/requestd.yml"];
-pub const RESPONSE_CLEANING_MAX: u64 = 10;
 
 pub type NodeData = json::Value;
 pub type Timestamp = DateTime<Utc>;
@@ -39,6 +63,7 @@ lazy_static! {
 				ConfigLoadingError::NoConfigFound => error!("no config found. First file in some of these locations will be loaded: {}", DEFAULT_CONF_FILES.join(", ")),
 				ConfigLoadingError::Io(e) => error!("error while loading config file: {}", e),
 				ConfigLoadingError::Yaml(e) => error!("error while parsing config: {}", e),
+				ConfigLoadingError::Invalid(e) => error!("invalid config: {}", e),
 			}
 
 			error!("generate a default config with '{} config -d'", env!("CARGO_BIN_NAME"));
@@ -91,31 +116,40 @@ fn start_collecting() {
 	let receiver = requester.get_receiver();
 
 
-	let collector = Arc::new(Mutex::new(Collector::new(requester.clone())));
-	collector.lock().unwrap().start_collector();
+	let mut collector = Collector::new(requester.clone());
+	collector.start_collector();
 
 
 	if CONFIG.web.is_some() {
-		let collector_c = collector.clone();
-		let web = web::Web::new(collector_c);
+		let web = web::Web::new(collector.handle());
 		std::thread::spawn(move || {
 			web.start();
 		});
 	}
 	if CONFIG.mqtt.is_some() {
-		let collector_c = collector.clone();
-		let mqtt = mqtt::Mqtt::new(collector_c);
+		let mqtt = mqtt::Mqtt::new(collector.handle());
 		std::thread::spawn(move || {
 			mqtt.start();
 		});
 	}
 	if CONFIG.zmq.is_some() {
-		let collector_c = collector.clone();
-		let zmq = zmq::Zmq::new(collector_c);
+		let zmq = zmq::Zmq::new(collector.handle());
 		std::thread::spawn(move || {
 			zmq.start();
 		});
 	}
+	if CONFIG.alertmanager.is_some() {
+		let alertmanager = alertmanager::Alertmanager::new(collector.handle());
+		std::thread::spawn(move || {
+			alertmanager.start();
+		});
+	}
+	if CONFIG.files.is_some() {
+		let files = filewriter::FileWriter::new(collector.handle());
+		std::thread::spawn(move || {
+			files.start();
+		});
+	}
 
 
 	debug!("starting requester");
@@ -127,29 +161,41 @@ fn start_collecting() {
 
 
 	trace!("start processing responses");
-	for node_response in &receiver {
-		// do some checks
-		if !node_response.response.is_object() {
-			warn!("a node at {} send invalid data", node_response.remote.to_string());
-			continue;
+	// wake up regularly, so snapshots are published even if no node responds
+	let tick = Duration::from_millis(CONFIG.requestd.publish_interval);
+	loop {
+		match receiver.recv_timeout(tick) {
+			Ok(node_response) => {
+				if let Some(node_res) = to_node_response(node_response) {
+					collector.receive(node_res);
+				}
+			}
+			Err(RecvTimeoutError::Timeout) => (),
+			Err(RecvTimeoutError::Disconnected) => break,
 		}
 
-		let nodeid = if let Some(nodeid) = get_nodeid_from_response_data(&node_response.response) {
-			nodeid
-		} else {
-			warn!("a node at {} has no nodeid", node_response.remote.to_string());
-			continue;
-		};
-
-		let node_res = NodeResponse {
-			nodeid: nodeid.to_string(),
-			remote: node_response.remote.ip(),
-			timestamp: node_response.timestamp,
-			data: node_response.response,
-		};
-
-		collector.lock().unwrap().receive(node_res);
+		collector.tick();
 	}
+
+	panic!("multicast receiver stopped");
+}
+
+
+/// further checks are done by the processors
+fn to_node_response(node_response: ResponddResponse) -> Option<NodeResponse> {
+	let nodeid = if let Some(nodeid) = get_nodeid_from_response_data(&node_response.response) {
+		nodeid
+	} else {
+		warn!("a node at {} has no nodeid", node_response.remote.to_string());
+		return None;
+	};
+
+	Some(NodeResponse {
+		nodeid: nodeid.to_string(),
+		remote: node_response.remote.ip(),
+		timestamp: node_response.timestamp,
+		data: node_response.response,
+	})
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -164,6 +210,15 @@ impl NodeResponse {
 	fn age(&self) -> u64 {
 		(Utc::now() - self.timestamp).num_seconds() as u64
 	}
+
+	/// the node responded within `offline_after`
+	pub fn is_online(&self) -> bool {
+		self.responded_within(CONFIG.requestd.offline_after)
+	}
+
+	pub fn responded_within(&self, seconds: u64) -> bool {
+		self.age() <= seconds
+	}
 }
 
 
@@ -191,7 +246,7 @@ pub enum Error {}
 
 pub trait Endpoint {
 	/// create and initialize new endpoint
-	fn new(c: Arc<Mutex<Collector>>) -> Self;
+	fn new(c: CollectorHandle) -> Self;
 
 	/// start the Endpoint
 	/// This method must never return. It will be started in a seperate thread
@@ -0,0 +1,401 @@
+//! node locations and mesh links for the map page and gis exports
+use crate::config::MapConfig;
+use crate::mesh::LinkType;
+use crate::topology::{escape_xml, Topology};
+use crate::{NodeId, NodeResponse};
+use serde::Serialize;
+use serde_json as json;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::Arc;
+
+
+#[derive(Debug, Serialize)]
+struct MapNode<'a> {
+	nodeid: &'a NodeId,
+	hostname: Option<&'a str>,
+	model: Option<&'a str>,
+	online: bool,
+	latitude: f64,
+	longitude: f64,
+	clients: Option<u64>,
+}
+
+
+#[derive(Debug, Serialize)]
+struct MapLink<'a> {
+	source: &'a NodeId,
+	target: &'a NodeId,
+	/// the worse direction, between 0 and 1
+	tq: Option<f64>,
+	#[serde(rename = "type")]
+	kind: LinkType,
+}
+
+
+fn location(response: &NodeResponse) -> Option<(f64, f64)> {
+	let location = response.data.pointer("/nodeinfo/location")?;
+	let latitude = location.get("latitude")?.as_f64()?;
+	let longitude = location.get("longitude")?.as_f64()?;
+
+	// 0,0 is what broken configs report
+	match (latitude, longitude) {
+		(lat, lon) if lat == 0.0 && lon == 0.0 => None,
+		(lat, lon) if lat.abs() <= 90.0 && lon.abs() <= 180.0 => Some((lat, lon)),
+		_ => None,
+	}
+}
+
+
+/// Nodes with a location and the links between them.
+/// Within `bbox` if given, so links to nodes outside of it are left out as well.
+fn located<'a>(
+	responses: &'a [Arc<NodeResponse>],
+	topology: &'a Topology,
+	bbox: Option<&BoundingBox>,
+) -> (Vec<MapNode<'a>>, Vec<MapLink<'a>>) {
+	let online: HashMap<&str, bool> = topology.nodes.iter().map(|n| (n.id.as_str(), n.online)).collect();
+
+	let nodes: Vec<MapNode> = responses
+		.iter()
+		.filter_map(|r| {
+			let (latitude, longitude) = location(r)?;
+			if let Some(bbox) = bbox {
+				if !bbox.contains(latitude, longitude) {
+					return None;
+				}
+			}
+
+			Some(MapNode {
+				nodeid: &r.nodeid,
+				hostname: r.data.pointer("/nodeinfo/hostname").and_then(|h| h.as_str()),
+				model: r.data.pointer("/nodeinfo/hardware/model").and_then(|m| m.as_str()),
+				online: online.get(r.nodeid.as_str()).copied().unwrap_or(false),
+				latitude,
+				longitude,
+				clients: r.data.pointer("/statistics/clients/total").and_then(|c| c.as_u64()),
+			})
+		})
+		.collect();
+
+	let located: HashSet<&str> = nodes.iter().map(|n| n.nodeid.as_str()).collect();
+	let links: Vec<MapLink> = topology
+		.links
+		.iter()
+		.filter(|l| located.contains(l.source.as_str()) && located.contains(l.target.as_str()))
+		.map(|l| MapLink {
+			source: &l.source,
+			target: &l.target,
+			tq: match (l.source_tq, l.target_tq) {
+				(Some(s), Some(t)) => Some(s.min(t)),
+				(s, t) => s.or(t),
+			},
+			kind: l.kind,
+		})
+		.collect();
+
+	(nodes, links)
+}
+
+/// both ends of every link
+fn link_ends<'a>(
+	nodes: &'a [MapNode],
+	links: &'a [MapLink],
+) -> impl Iterator<Item = (&'a MapLink<'a>, &'a MapNode<'a>, &'a MapNode<'a>)> {
+	let by_id: HashMap<&str, &MapNode> = nodes.iter().map(|n| (n.nodeid.as_str(), n)).collect();
+	links
+		.iter()
+		.filter_map(move |l| Some((l, *by_id.get(l.source.as_str())?, *by_id.get(l.target.as_str())?)))
+}
+
+
+/// data of the map page
+pub fn render(responses: &[Arc<NodeResponse>], topology: &Topology, config: &MapConfig) -> Vec<u8> {
+	let (nodes, links) = located(responses, topology, None);
+
+	json::to_vec(&json::json!({
+		"tiles": config.tiles,
+		"attribution": config.attribution,
+		"nodes": nodes,
+		"links": links,
+	}))
+	.unwrap()
+}
+
+
+/// `minlon,minlat,maxlon,maxlat` like in geojson
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox {
+	min_lon: f64,
+	min_lat: f64,
+	max_lon: f64,
+	max_lat: f64,
+}
+
+impl BoundingBox {
+	pub fn parse(s: &str) -> Result<Self, String> {
+		const FORMAT: &str = "bbox must be minlon,minlat,maxlon,maxlat";
+
+		let values = s
+			.split(',')
+			.map(|v| v.trim().parse::<f64>())
+			.collect::<Result<Vec<f64>, _>>()
+			.map_err(|_| FORMAT)?;
+
+		let (min_lon, min_lat, max_lon, max_lat) = match values[..] {
+			[min_lon, min_lat, max_lon, max_lat] => (min_lon, min_lat, max_lon, max_lat),
+			_ => return Err(FORMAT.to_string()),
+		};
+
+		// also false for nan and inf
+		if !(min_lon.abs() <= 180.0 && max_lon.abs() <= 180.0) {
+			return Err("longitudes must be between -180 and 180".to_string());
+		}
+		if !(min_lat.abs() <= 90.0 && max_lat.abs() <= 90.0) {
+			return Err("latitudes must be between -90 and 90".to_string());
+		}
+		if min_lat > max_lat {
+			return Err("minlat is greater than maxlat".to_string());
+		}
+
+		Ok(Self {
+			min_lon,
+			min_lat,
+			max_lon,
+			max_lat,
+		})
+	}
+
+	fn contains(&self, lat: f64, lon: f64) -> bool {
+		let lon_inside = if self.min_lon <= self.max_lon {
+			self.min_lon <= lon && lon <= self.max_lon
+		} else {
+			// crosses the antimeridian
+			lon >= self.min_lon || lon <= self.max_lon
+		};
+
+		lon_inside && self.min_lat <= lat && lat <= self.max_lat
+	}
+}
+
+
+/// nodes as points and links as line strings
+pub fn to_geojson(responses: &[Arc<NodeResponse>], topology: &Topology, bbox: Option<&BoundingBox>) -> Vec<u8> {
+	let (nodes, links) = located(responses, topology, bbox);
+
+	let points = nodes.iter().map(|n| {
+		json::json!({
+			"type": "Feature",
+			"geometry": {"type": "Point", "coordinates": [n.longitude, n.latitude]},
+			"properties": {
+				"feature": "node",
+				"nodeid": n.nodeid,
+				"hostname": n.hostname,
+				"model": n.model,
+				"clients": n.clients,
+				"online": n.online,
+			},
+		})
+	});
+	let lines = link_ends(&nodes, &links).map(|(l, a, b)| {
+		json::json!({
+			"type": "Feature",
+			"geometry": {"type": "LineString", "coordinates": [[a.longitude, a.latitude], [b.longitude, b.latitude]]},
+			"properties": {
+				"feature": "link",
+				"source": l.source,
+				"target": l.target,
+				"tq": l.tq,
+				"link_type": l.kind,
+			},
+		})
+	});
+
+	json::to_vec(&json::json!({
+		"type": "FeatureCollection",
+		"features": points.chain(lines).collect::<Vec<_>>(),
+	}))
+	.unwrap()
+}
+
+
+/// for google earth
+pub fn to_kml(responses: &[Arc<NodeResponse>], topology: &Topology, bbox: Option<&BoundingBox>) -> Vec<u8> {
+	let (nodes, links) = located(responses, topology, bbox);
+
+	let mut out = String::from(concat!(
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+		"<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n<name>requestd nodes</name>\n",
+		"<Style id=\"online\"><IconStyle><color>ff1a9c1a</color></IconStyle></Style>\n",
+		"<Style id=\"offline\"><IconStyle><color>ff2020d0</color></IconStyle></Style>\n",
+		"<Style id=\"link\"><LineStyle><color>ff888888</color><width>2</width></LineStyle></Style>\n",
+		"<Folder>\n<name>nodes</name>\n",
+	));
+
+	for n in &nodes {
+		let data = [
+			("nodeid", n.nodeid.to_string()),
+			("model", n.model.unwrap_or_default().to_string()),
+			("clients", n.clients.map(|c| c.to_string()).unwrap_or_default()),
+			("online", n.online.to_string()),
+		];
+
+		writeln!(out, "<Placemark>\n<name>{}</name>", escape_xml(n.hostname.unwrap_or(n.nodeid))).unwrap();
+		let style = if n.online { "online" } else { "offline" };
+		writeln!(out, "<styleUrl>#{}</styleUrl>\n<ExtendedData>", style).unwrap();
+		for (name, value) in &data {
+			writeln!(out, "<Data name=\"{}\"><value>{}</value></Data>", name, escape_xml(value)).unwrap();
+		}
+		out.push_str("</ExtendedData>\n");
+		writeln!(out, "<Point><coordinates>{},{}</coordinates></Point>\n</Placemark>", n.longitude, n.latitude).unwrap();
+	}
+
+	out.push_str("</Folder>\n<Folder>\n<name>links</name>\n");
+	for (l, a, b) in link_ends(&nodes, &links) {
+		writeln!(
+			out,
+			concat!(
+				"<Placemark>\n<name>{} - {}</name>\n<styleUrl>#link</styleUrl>\n",
+				"<LineString><coordinates>{},{} {},{}</coordinates></LineString>\n</Placemark>",
+			),
+			escape_xml(a.hostname.unwrap_or(l.source)),
+			escape_xml(b.hostname.unwrap_or(l.target)),
+			a.longitude,
+			a.latitude,
+			b.longitude,
+			b.latitude,
+		)
+		.unwrap();
+	}
+
+	out.push_str("</Folder>\n</Document>\n</kml>\n");
+	out.into_bytes()
+}
+
+
+#[test]
+fn located_nodes_and_exports() {
+	use crate::mesh::Link;
+	use crate::topology::TopologyNode;
+
+	let response = |nodeid: &str, location: json::Value| Arc::new(NodeResponse {
+		nodeid: nodeid.to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::Utc::now(),
+		data: json::json!({"nodeinfo": {"hostname": nodeid, "location": location}}),
+	});
+	let responses = vec![
+		response("a", json::json!({"latitude": 53.87, "longitude": 10.69})),
+		response("b", json::json!({"latitude": 0.0, "longitude": 0.0})),
+		response("c", json::json!(null)),
+		response("d", json::json!({"latitude": 153.0, "longitude": 10.0})),
+		response("e", json::json!({"latitude": 53.9, "longitude": 10.7})),
+		// outside of the bbox below
+		response("f", json::json!({"latitude": 48.1, "longitude": 11.5})),
+	];
+
+	let located: Vec<&str> = responses.iter().filter(|r| location(r).is_some()).map(|r| r.nodeid.as_str()).collect();
+	assert_eq!(located, vec!["a", "e", "f"]);
+
+	let link = |source: &str, target: &str| Link {
+		source: source.to_string(),
+		target: target.to_string(),
+		source_addr: format!("02:00:00:00:00:0{}", source),
+		target_addr: format!("02:00:00:00:00:0{}", target),
+		source_tq: Some(0.8),
+		target_tq: Some(0.6),
+		kind: LinkType::Wifi,
+	};
+	let topology = Topology {
+		nodes: ["a", "e", "f"]
+			.iter()
+			.map(|id| TopologyNode { id: id.to_string(), hostname: Some(id.to_string()), online: true })
+			.collect(),
+		links: vec![link("a", "e"), link("a", "f")],
+		index: crate::mesh::MacIndex::new(&responses),
+	};
+	let map: json::Value = json::from_slice(&render(&responses, &topology, &MapConfig::default())).unwrap();
+	assert_eq!(map["nodes"][0]["hostname"], "a");
+	assert_eq!(map["nodes"].as_array().unwrap().len(), 3);
+	assert_eq!(map["tiles"], json::Value::Null);
+
+	let bbox = BoundingBox::parse("10,53,11,54").unwrap();
+	let geojson: json::Value = json::from_slice(&to_geojson(&responses, &topology, Some(&bbox))).unwrap();
+	let features = geojson["features"].as_array().unwrap();
+	assert_eq!(features[0]["geometry"]["coordinates"], json::json!([10.69, 53.87]));
+	assert_eq!(features[0]["properties"]["nodeid"], "a");
+	// the link to f is left out with f
+	let lines: Vec<&json::Value> = features.iter().filter(|f| f["geometry"]["type"] == "LineString").collect();
+	assert_eq!(lines.len(), 1);
+	assert_eq!(lines[0]["geometry"]["coordinates"], json::json!([[10.69, 53.87], [10.7, 53.9]]));
+	assert_eq!(lines[0]["properties"]["target"], "e");
+	assert_eq!(lines[0]["properties"]["tq"], 0.6);
+
+	let outside = BoundingBox::parse("-10,0,-5,5").unwrap();
+	let kml = |bbox| String::from_utf8(to_kml(&responses, &topology, Some(bbox))).unwrap();
+	let inside = kml(&bbox);
+	assert!(inside.contains("<coordinates>10.69,53.87</coordinates>"));
+	assert!(inside.contains("<name>a - e</name>"));
+	assert!(inside.contains("<LineString><coordinates>10.69,53.87 10.7,53.9</coordinates></LineString>"));
+	assert!(!inside.contains("<name>a - f</name>"));
+	assert!(!kml(&outside).contains("<Placemark>"));
+
+	// the whole world has both links
+	let world = String::from_utf8(to_kml(&responses, &topology, None)).unwrap();
+	assert!(world.contains("<name>a - f</name>"));
+}
+
+#[test]
+fn invalid_bounding_boxes() {
+	assert!(BoundingBox::parse("10,53,11").is_err());
+	assert!(BoundingBox::parse("10,54,11,53").is_err());
+	assert!(BoundingBox::parse("NaN,53,11,54").is_err());
+	assert!(BoundingBox::parse("10,53,inf,54").is_err());
+	assert!(BoundingBox::parse("10,-91,11,54").is_err());
+	assert!(BoundingBox::parse("-181,53,11,54").is_err());
+	assert!(BoundingBox::parse("170,-90,-170,90").is_ok());
+}
+
+#[test]
+fn map_links_and_online() {
+	use crate::mesh::Link;
+	use crate::topology::TopologyNode;
+
+	let response = |nodeid: &str, latitude: f64| Arc::new(NodeResponse {
+		nodeid: nodeid.to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::Utc::now(),
+		data: json::json!({"nodeinfo": {"location": {"latitude": latitude, "longitude": 10.0}}}),
+	});
+	let responses = vec![response("a", 53.0), response("b", 53.1), response("c", 53.2)];
+
+	let link = |target: &str, source_tq, target_tq, kind| Link {
+		source: "a".to_string(),
+		target: target.to_string(),
+		source_addr: "02:00:00:00:00:0a".to_string(),
+		target_addr: format!("02:00:00:00:00:0{}", target),
+		source_tq,
+		target_tq,
+		kind,
+	};
+	let topology = Topology {
+		nodes: vec![
+			TopologyNode { id: "a".to_string(), hostname: None, online: true },
+			TopologyNode { id: "b".to_string(), hostname: None, online: true },
+			// c is missing from the topology, so it is offline
+		],
+		links: vec![link("b", Some(0.9), Some(0.4), LinkType::Wifi), link("c", None, Some(0.7), LinkType::Vpn)],
+		index: crate::mesh::MacIndex::new(&responses),
+	};
+
+	let map: json::Value = json::from_slice(&render(&responses, &topology, &MapConfig::default())).unwrap();
+	let online: Vec<bool> = map["nodes"].as_array().unwrap().iter().map(|n| n["online"].as_bool().unwrap()).collect();
+	assert_eq!(online, vec![true, true, false]);
+
+	assert_eq!(map["links"][0]["target"], "b");
+	assert_eq!(map["links"][0]["tq"], 0.4);
+	assert_eq!(map["links"][0]["type"], "wifi");
+	// only one side reported a tq
+	assert_eq!(map["links"][1]["tq"], 0.7);
+	assert_eq!(map["links"][1]["type"], "vpn");
+}
@@ -0,0 +1,221 @@
+//! interprets the batman-adv neighbours of the nodes
+use crate::{Mac, NodeId, NodeResponse};
+use serde::Serialize;
+use serde_json as json;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkType {
+	Wifi,
+	Vpn,
+	Other,
+}
+
+impl LinkType {
+	/// type of a gluon mesh interface group
+	fn from_interface_type(kind: &str) -> Self {
+		match kind {
+			"wireless" => Self::Wifi,
+			"tunnel" => Self::Vpn,
+			_ => Self::Other,
+		}
+	}
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Wifi => "wifi",
+			Self::Vpn => "vpn",
+			Self::Other => "other",
+		}
+	}
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Interface {
+	pub nodeid: NodeId,
+	pub kind: LinkType,
+}
+
+
+/// maps the mac addresses of all known interfaces to their node
+#[derive(Clone, Debug, Default)]
+pub struct MacIndex {
+	interfaces: HashMap<Mac, Interface>,
+}
+
+impl MacIndex {
+	pub fn new(responses: &[Arc<NodeResponse>]) -> Self {
+		let mut index = Self::default();
+
+		for response in responses {
+			let network = match response.data.pointer("/nodeinfo/network") {
+				Some(n) => n,
+				None => continue,
+			};
+
+			let mesh = network.get("mesh").and_then(|m| m.as_object());
+			for bat in mesh.iter().flat_map(|m| m.values()) {
+				let types = bat.get("interfaces").and_then(|i| i.as_object());
+				for (kind, macs) in types.iter().flat_map(|t| t.iter()) {
+					for mac in macs.as_array().iter().flat_map(|m| m.iter()) {
+						index.insert(mac, &response.nodeid, LinkType::from_interface_type(kind));
+					}
+				}
+			}
+
+			// older firmwares only report a flat list
+			for mac in network.get("mesh_interfaces").and_then(|m| m.as_array()).iter().flat_map(|m| m.iter()) {
+				index.insert(mac, &response.nodeid, LinkType::Other);
+			}
+
+			if let Some(mac) = network.get("mac") {
+				if !mac.as_str().is_some_and(|m| index.interfaces.contains_key(m)) {
+					index.insert(mac, &response.nodeid, LinkType::Other);
+				}
+			}
+		}
+
+		index
+	}
+
+	fn insert(&mut self, mac: &json::Value, nodeid: &str, kind: LinkType) {
+		if let Some(mac) = mac.as_str() {
+			self.interfaces.insert(mac.to_string(), Interface {
+				nodeid: nodeid.to_string(),
+				kind,
+			});
+		}
+	}
+
+	pub fn get(&self, mac: &str) -> Option<&Interface> {
+		self.interfaces.get(mac)
+	}
+
+	pub fn nodeid(&self, mac: &str) -> Option<&NodeId> {
+		self.get(mac).map(|i| &i.nodeid)
+	}
+}
+
+
+/// a batman-adv neighbour as reported by the source node
+#[derive(Clone, Debug, Serialize)]
+pub struct Neighbour {
+	pub source: NodeId,
+	pub target: NodeId,
+	pub source_addr: Mac,
+	pub target_addr: Mac,
+	/// transmit quality between 0 and 1
+	pub tq: f64,
+	pub kind: LinkType,
+}
+
+/// all batman-adv neighbours of all nodes whose neighbour is a known node
+pub fn neighbours(responses: &[Arc<NodeResponse>], index: &MacIndex) -> Vec<Neighbour> {
+	let mut neighbours = vec![];
+
+	for response in responses {
+		let batadv = match response.data.pointer("/neighbours/batadv").and_then(|b| b.as_object()) {
+			Some(b) => b,
+			None => continue,
+		};
+
+		for (source_addr, interface) in batadv {
+			let macs = interface.get("neighbours").and_then(|n| n.as_object());
+			for (target_addr, neighbour) in macs.iter().flat_map(|n| n.iter()) {
+				let target = match index.nodeid(target_addr) {
+					Some(t) => t,
+					None => continue,
+				};
+
+				let kind = index
+					.get(source_addr)
+					.or_else(|| index.get(target_addr))
+					.map_or(LinkType::Other, |i| i.kind);
+
+				neighbours.push(Neighbour {
+					source: response.nodeid.clone(),
+					target: target.clone(),
+					source_addr: source_addr.clone(),
+					target_addr: target_addr.clone(),
+					tq: neighbour.get("tq").and_then(|t| t.as_f64()).unwrap_or(0.0) / 255.0,
+					kind,
+				});
+			}
+		}
+	}
+
+	neighbours
+}
+
+
+/// a connection between two interfaces, merged from the neighbours of both sides
+#[derive(Clone, Debug, Serialize)]
+pub struct Link {
+	pub source: NodeId,
+	pub target: NodeId,
+	pub source_addr: Mac,
+	pub target_addr: Mac,
+	/// tq from source to target as reported by the source
+	pub source_tq: Option<f64>,
+	/// tq from target to source as reported by the target
+	pub target_tq: Option<f64>,
+	pub kind: LinkType,
+}
+
+pub fn links(neighbours: &[Neighbour]) -> Vec<Link> {
+	let mut links: HashMap<(Mac, Mac), Link> = HashMap::new();
+
+	for n in neighbours {
+		if let Some(link) = links.get_mut(&(n.target_addr.clone(), n.source_addr.clone())) {
+			link.target_tq = Some(n.tq);
+			continue;
+		}
+
+		links.insert((n.source_addr.clone(), n.target_addr.clone()), Link {
+			source: n.source.clone(),
+			target: n.target.clone(),
+			source_addr: n.source_addr.clone(),
+			target_addr: n.target_addr.clone(),
+			source_tq: Some(n.tq),
+			target_tq: None,
+			kind: n.kind,
+		});
+	}
+
+	let mut links: Vec<Link> = links.into_values().collect();
+	links.sort_by(|a, b| (&a.source_addr, &a.target_addr).cmp(&(&b.source_addr, &b.target_addr)));
+	links
+}
+
+
+
+#[test]
+fn links_are_merged() {
+	let node = |nodeid: &str, mac: &str, neighbour: &str, tq: u64| Arc::new(NodeResponse {
+		nodeid: nodeid.to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::Utc::now(),
+		data: json::json!({
+			"nodeinfo": {"network": {"mesh": {"bat0": {"interfaces": {"wireless": [mac]}}}}},
+			"neighbours": {"batadv": {mac: {"neighbours": {neighbour: {"tq": tq}}}}},
+		}),
+	});
+	let responses = vec![
+		node("aa", "02:00:00:00:00:aa", "02:00:00:00:00:bb", 255),
+		node("bb", "02:00:00:00:00:bb", "02:00:00:00:00:aa", 0),
+	];
+
+	let index = MacIndex::new(&responses);
+	assert_eq!(index.nodeid("02:00:00:00:00:bb"), Some(&"bb".to_string()));
+
+	let links = links(&neighbours(&responses, &index));
+	assert_eq!(links.len(), 1);
+	assert_eq!(links[0].source, "aa");
+	assert_eq!(links[0].source_tq, Some(1.0));
+	assert_eq!(links[0].target_tq, Some(0.0));
+	assert_eq!(links[0].kind, LinkType::Wifi);
+}
@@ -0,0 +1,175 @@
+//! meshviewer.json as used by https://github.com/ffrgb/meshviewer
+use crate::collector::Snapshot;
+use crate::legacy::memory_usage;
+use crate::mesh::MacIndex;
+use crate::{NodeResponse, Timestamp};
+use chrono::Duration;
+use serde_json as json;
+use serde_json::Value;
+
+/// timestamp format meshviewer expects
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%z";
+
+
+pub fn render(snapshot: &Snapshot) -> Vec<u8> {
+	json::to_vec(&build(snapshot)).unwrap()
+}
+
+pub fn build(snapshot: &Snapshot) -> Value {
+	let topology = snapshot.topology();
+
+	let nodes: Vec<Value> = snapshot.responses().iter().map(|r| node(r, snapshot, &topology.index)).collect();
+
+	let links: Vec<Value> = topology
+		.links
+		.iter()
+		.map(|l| {
+			json::json!({
+				"type": l.kind,
+				"source": l.source,
+				"target": l.target,
+				"source_tq": l.source_tq.unwrap_or(0.0),
+				"target_tq": l.target_tq.unwrap_or(0.0),
+				"source_addr": l.source_addr,
+				"target_addr": l.target_addr,
+			})
+		})
+		.collect();
+
+	json::json!({
+		"timestamp": format_time(snapshot.created()),
+		"nodes": nodes,
+		"links": links,
+	})
+}
+
+
+fn node(response: &NodeResponse, snapshot: &Snapshot, index: &MacIndex) -> Value {
+	let data = &response.data;
+	let get = |pointer: &str| data.pointer(pointer).cloned().unwrap_or(Value::Null);
+	let number = |pointer: &str| data.pointer(pointer).and_then(|v| v.as_f64());
+	let nodeid_of = |pointer: &str| {
+		data.pointer(pointer)
+			.and_then(|m| m.as_str())
+			.and_then(|m| index.nodeid(m))
+			.map_or(Value::Null, |n| Value::String(n.clone()))
+	};
+
+	let clients = number("/statistics/clients/total").unwrap_or(0.0);
+	let wifi24 = number("/statistics/clients/wifi24").unwrap_or(0.0);
+	let wifi5 = number("/statistics/clients/wifi5").unwrap_or(0.0);
+	let wifi = number("/statistics/clients/wifi").unwrap_or(wifi24 + wifi5);
+
+	// meshviewer wants the time of the last boot
+	let uptime = number("/statistics/uptime")
+		.map(|u| format_time(response.timestamp - Duration::seconds(u as i64)));
+
+	let firstseen = snapshot.first_seen(&response.nodeid).unwrap_or(response.timestamp);
+
+	json::json!({
+		"firstseen": format_time(firstseen),
+		"lastseen": format_time(response.timestamp),
+		"is_online": snapshot.is_online(response),
+		"is_gateway": data.pointer("/nodeinfo/vpn").and_then(|v| v.as_bool()).unwrap_or(false),
+		"clients": clients,
+		"clients_wifi24": wifi24,
+		"clients_wifi5": wifi5,
+		"clients_other": (clients - wifi).max(0.0),
+		"rootfs_usage": get("/statistics/rootfs_usage"),
+		"loadavg": get("/statistics/loadavg"),
+		"memory_usage": memory_usage(data),
+		"uptime": uptime,
+		"gateway_nexthop": nodeid_of("/statistics/gateway_nexthop"),
+		"gateway": nodeid_of("/statistics/gateway"),
+		"gateway6": nodeid_of("/statistics/gateway6"),
+		"node_id": response.nodeid,
+		"mac": get("/nodeinfo/network/mac"),
+		"addresses": data.pointer("/nodeinfo/network/addresses").cloned().unwrap_or_else(|| json::json!([])),
+		"site_code": get("/nodeinfo/system/site_code"),
+		"domain": get("/nodeinfo/system/domain_code"),
+		"hostname": get("/nodeinfo/hostname"),
+		"owner": get("/nodeinfo/owner/contact"),
+		"location": get("/nodeinfo/location"),
+		"firmware": {
+			"base": get("/nodeinfo/software/firmware/base"),
+			"release": get("/nodeinfo/software/firmware/release"),
+		},
+		"autoupdater": {
+			"enabled": data.pointer("/nodeinfo/software/autoupdater/enabled").and_then(|v| v.as_bool()).unwrap_or(false),
+			"branch": get("/nodeinfo/software/autoupdater/branch"),
+		},
+		"nproc": get("/nodeinfo/hardware/nproc"),
+		"model": get("/nodeinfo/hardware/model"),
+	})
+}
+
+
+pub fn format_time(t: Timestamp) -> String {
+	t.format(TIME_FORMAT).to_string()
+}
+
+
+
+#[test]
+fn meshviewer_nodes() {
+	let now = chrono::TimeZone::timestamp_opt(&chrono::Utc, 1_600_000_000, 0).unwrap();
+	let response = |nodeid: &str, age: i64, data: Value| {
+		std::sync::Arc::new(NodeResponse {
+			nodeid: nodeid.to_string(),
+			remote: "fe80::1".parse().unwrap(),
+			timestamp: chrono::Utc::now() - Duration::seconds(age),
+			data,
+		})
+	};
+	let online = response(
+		"c04a00dd692a",
+		10,
+		json::json!({
+			"nodeinfo": {
+				"hostname": "ffhl-online",
+				"location": {"latitude": 53.87, "longitude": 10.69},
+			},
+			"statistics": {
+				"uptime": 3600.0,
+				"memory": {"total": 1000, "available": 250, "free": 100, "buffers": 100, "cached": 100},
+			},
+		}),
+	);
+	let offline = response(
+		"c04a00dd692b",
+		500,
+		json::json!({
+			"statistics": {"memory": {"total": 1000, "free": 100, "buffers": 100, "cached": 300}},
+		}),
+	);
+	let first_seen = std::iter::once(("c04a00dd692a".to_string(), now)).collect();
+	let snapshot = Snapshot::new(
+		1,
+		now,
+		vec![online.clone(), offline.clone()],
+		180,
+		first_seen,
+		Default::default(),
+		vec![],
+		vec![],
+	);
+
+	let meshviewer = build(&snapshot);
+	let nodes = meshviewer["nodes"].as_array().unwrap();
+	assert_eq!(meshviewer["timestamp"], "2020-09-13T12:26:40+0000");
+
+	assert_eq!(nodes[0]["node_id"], "c04a00dd692a");
+	assert_eq!(nodes[0]["hostname"], "ffhl-online");
+	assert_eq!(nodes[0]["is_online"], true);
+	assert_eq!(nodes[0]["firstseen"], "2020-09-13T12:26:40+0000");
+	assert_eq!(nodes[0]["lastseen"], format_time(online.timestamp));
+	assert_eq!(nodes[0]["uptime"], format_time(online.timestamp - Duration::seconds(3600)));
+	assert_eq!(nodes[0]["location"], json::json!({"latitude": 53.87, "longitude": 10.69}));
+	assert_eq!(nodes[0]["memory_usage"], 0.75);
+
+	// never seen before, so first seen with this response
+	assert_eq!(nodes[1]["is_online"], false);
+	assert_eq!(nodes[1]["firstseen"], format_time(offline.timestamp));
+	assert_eq!(nodes[1]["location"], Value::Null);
+	assert_eq!(nodes[1]["memory_usage"], 0.5);
+}
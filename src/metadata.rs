@@ -0,0 +1,230 @@
+//! attaches our own data like owner or maintenance contact to the responses
+use crate::processor::{Processor, Verdict};
+use crate::NodeResponse;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde_json as json;
+use serde_yaml as yaml;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+/// how often the file is checked for changes
+const RELOAD_CHECK: Duration = Duration::from_secs(10);
+/// csv columns that are split into a list at `;`
+const LIST_COLUMNS: &[&str] = &["tags"];
+
+
+#[derive(Debug)]
+pub enum MetadataError {
+	Io(io::Error),
+	Yaml(yaml::Error),
+	Csv(csv::Error),
+	/// an entry has neither a `nodeid` nor a `mac`
+	NoKey,
+}
+
+impl From<io::Error> for MetadataError {
+	fn from(e: io::Error) -> Self {
+		Self::Io(e)
+	}
+}
+
+impl From<yaml::Error> for MetadataError {
+	fn from(e: yaml::Error) -> Self {
+		Self::Yaml(e)
+	}
+}
+
+impl From<csv::Error> for MetadataError {
+	fn from(e: csv::Error) -> Self {
+		Self::Csv(e)
+	}
+}
+
+impl Display for MetadataError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Io(e) => write!(f, "{}", e),
+			Self::Yaml(e) => write!(f, "{}", e),
+			Self::Csv(e) => write!(f, "{}", e),
+			Self::NoKey => write!(f, "entry without nodeid or mac"),
+		}
+	}
+}
+
+
+/// metadata by node id and by mac
+#[derive(Debug, Default)]
+struct Entries {
+	by_nodeid: HashMap<String, json::Value>,
+	by_mac: HashMap<String, json::Value>,
+}
+
+impl Entries {
+	fn from_rows(rows: Vec<BTreeMap<String, json::Value>>) -> Result<Self, MetadataError> {
+		let mut entries = Self::default();
+
+		for mut row in rows {
+			let nodeid = row.remove("nodeid");
+			let mac = row.remove("mac");
+			let data = json::Value::Object(row.into_iter().collect());
+
+			match (nodeid, mac) {
+				(Some(json::Value::String(nodeid)), _) => entries.by_nodeid.insert(nodeid, data),
+				(_, Some(json::Value::String(mac))) => entries.by_mac.insert(mac.to_lowercase(), data),
+				_ => return Err(MetadataError::NoKey),
+			};
+		}
+
+		Ok(entries)
+	}
+
+	fn parse_yaml(content: &str) -> Result<Self, MetadataError> {
+		Self::from_rows(yaml::from_str(content)?)
+	}
+
+	fn parse_csv<R: Read>(reader: R) -> Result<Self, MetadataError> {
+		let mut reader = csv::Reader::from_reader(reader);
+		let mut rows = vec![];
+
+		for row in reader.deserialize() {
+			let row: BTreeMap<String, String> = row?;
+			let row = row
+				.into_iter()
+				.filter(|(_, v)| !v.is_empty())
+				.map(|(k, v)| {
+					let value = if LIST_COLUMNS.contains(&k.as_str()) {
+						json::json!(v.split(';').map(|t| t.trim()).filter(|t| !t.is_empty()).collect::<Vec<_>>())
+					} else {
+						json::Value::String(v)
+					};
+					(k, value)
+				})
+				.collect();
+			rows.push(row);
+		}
+
+		Self::from_rows(rows)
+	}
+
+	fn get(&self, response: &NodeResponse) -> Option<&json::Value> {
+		self.by_nodeid.get(&response.nodeid).or_else(|| {
+			let mac = response.data.pointer("/nodeinfo/network/mac")?.as_str()?;
+			self.by_mac.get(&mac.to_lowercase())
+		})
+	}
+}
+
+
+/// Adds the entry of the node from a yaml or csv file under `key`.
+/// The file is reloaded as soon as it changes.
+pub struct Metadata {
+	file: PathBuf,
+	key: String,
+	entries: Entries,
+	modified: Option<SystemTime>,
+	last_check: Instant,
+}
+
+impl Metadata {
+	pub fn new(file: PathBuf, key: String) -> Self {
+		let mut metadata = Self {
+			file,
+			key,
+			entries: Entries::default(),
+			modified: None,
+			last_check: Instant::now(),
+		};
+		metadata.reload_if_changed();
+		metadata
+	}
+
+	fn reload_if_changed(&mut self) {
+		self.last_check = Instant::now();
+
+		let modified = match fs::metadata(&self.file).and_then(|m| m.modified()) {
+			Ok(m) => m,
+			Err(e) => {
+				warn!("cannot read metadata file {}: {}", self.file.display(), e);
+				return;
+			}
+		};
+		if self.modified == Some(modified) {
+			return;
+		}
+		// don't retry a broken file until it changes again
+		self.modified = Some(modified);
+
+		match self.load() {
+			Ok(entries) => {
+				info!(
+					"loaded metadata of {} nodes from {}",
+					entries.by_nodeid.len() + entries.by_mac.len(),
+					self.file.display()
+				);
+				self.entries = entries;
+			}
+			Err(e) => error!("cannot load metadata file {}: {}. keeping the old data", self.file.display(), e),
+		}
+	}
+
+	fn load(&self) -> Result<Entries, MetadataError> {
+		let content = fs::read_to_string(&self.file)?;
+
+		let is_csv = self.file.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+		if is_csv {
+			Entries::parse_csv(content.as_bytes())
+		} else {
+			Entries::parse_yaml(&content)
+		}
+	}
+}
+
+impl Processor for Metadata {
+	fn name(&self) -> &str {
+		"metadata"
+	}
+
+	fn process(&mut self, response: &mut NodeResponse) -> Verdict {
+		if self.last_check.elapsed() > RELOAD_CHECK {
+			self.reload_if_changed();
+		}
+
+		if let Some(meta) = self.entries.get(response).cloned() {
+			if let Some(data) = response.data.as_object_mut() {
+				data.insert(self.key.clone(), meta);
+			}
+		}
+
+		Verdict::Keep
+	}
+}
+
+
+
+#[test]
+fn metadata_by_nodeid_and_mac() {
+	let csv = "nodeid,mac,owner,tags\nc04a00dd692a,,ffhl e.V.,backbone; roof\n,60:E3:27:10:F3:EC,someone,\n";
+	let entries = Entries::parse_csv(csv.as_bytes()).unwrap();
+
+	let response = |nodeid: &str, mac: &str| NodeResponse {
+		nodeid: nodeid.to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::Utc::now(),
+		data: json::json!({"nodeinfo": {"network": {"mac": mac}}}),
+	};
+
+	assert_eq!(
+		entries.get(&response("c04a00dd692a", "c0:4a:00:dd:69:2a")),
+		Some(&json::json!({"owner": "ffhl e.V.", "tags": ["backbone", "roof"]}))
+	);
+	assert_eq!(
+		entries.get(&response("60e32710f3ec", "60:e3:27:10:f3:ec")),
+		Some(&json::json!({"owner": "someone"}))
+	);
+	assert!(entries.get(&response("000000000000", "00:00:00:00:00:00")).is_none());
+}
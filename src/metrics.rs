@@ -0,0 +1,308 @@
+//! renders node statistics in the prometheus text exposition format
+//! https://prometheus.io/docs/instrumenting/exposition_formats/
+use crate::alerts::Alert;
+use crate::collector::SubscriberStats;
+use crate::inventory::{InventoryNode, Status};
+use crate::rates::{RATES_KEY, TRAFFIC};
+use crate::reboots::RebootHistory;
+use crate::NodeId;
+use crate::NodeResponse;
+use serde_json as json;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Arc;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// labels taken from nodeinfo and attached to every node metric
+pub const NODE_LABELS: &[(&str, &str)] = &[
+	("hostname", "/nodeinfo/hostname"),
+	("site", "/nodeinfo/system/site_code"),
+	("domain", "/nodeinfo/system/domain_code"),
+	("model", "/nodeinfo/hardware/model"),
+	("firmware", "/nodeinfo/software/firmware/release"),
+];
+
+
+struct Family {
+	name: &'static str,
+	help: &'static str,
+	kind: &'static str,
+	samples: Vec<(String, f64)>,
+}
+
+impl Family {
+	fn new(name: &'static str, kind: &'static str, help: &'static str) -> Self {
+		Self {
+			name,
+			help,
+			kind,
+			samples: vec![],
+		}
+	}
+
+	fn add(&mut self, labels: String, value: f64) {
+		self.samples.push((labels, value));
+	}
+
+	fn render(&self, out: &mut String) {
+		if self.samples.is_empty() {
+			return;
+		}
+
+		writeln!(out, "# HELP {} {}", self.name, self.help).unwrap();
+		writeln!(out, "# TYPE {} {}", self.name, self.kind).unwrap();
+		for (labels, value) in &self.samples {
+			writeln!(out, "{}{{{}}} {}", self.name, labels, value).unwrap();
+		}
+	}
+}
+
+
+#[derive(Default)]
+struct Aggregate {
+	nodes: u64,
+	clients: f64,
+}
+
+
+/// render all node metrics and the per site/domain aggregates
+pub fn render(responses: &[Arc<NodeResponse>]) -> String {
+	let mut clients = Family::new("requestd_node_clients", "gauge", "number of clients connected to the node");
+	let mut load = Family::new("requestd_node_load", "gauge", "load average of the node");
+	let mut memory = Family::new("requestd_node_memory_bytes", "gauge", "memory of the node");
+	let mut uptime = Family::new("requestd_node_uptime_seconds", "gauge", "uptime of the node");
+	let mut traffic = Family::new("requestd_node_traffic_bytes_total", "counter", "bytes transferred by the node");
+	let mut packets = Family::new("requestd_node_traffic_packets_total", "counter", "packets transferred by the node");
+	let mut byte_rate = Family::new("requestd_node_traffic_bytes_per_second", "gauge", "bytes per second transferred by the node");
+	let mut packet_rate = Family::new("requestd_node_traffic_packets_per_second", "gauge", "packets per second transferred by the node");
+	let mut gateway_tq = Family::new("requestd_node_gateway_tq", "gauge", "transmit quality to the selected gateway");
+	let mut processes = Family::new("requestd_node_processes", "gauge", "number of processes on the node");
+	let mut sites = Family::new("requestd_site_nodes", "gauge", "number of nodes per site and domain");
+	let mut site_clients = Family::new("requestd_site_clients", "gauge", "number of clients per site and domain");
+
+	let mut aggregates: BTreeMap<(String, String), Aggregate> = BTreeMap::new();
+
+	for response in responses {
+		let labels = node_labels(response);
+		let statistics = response.data.pointer("/statistics");
+
+		let site = label_value(&response.data, "/nodeinfo/system/site_code");
+		let domain = label_value(&response.data, "/nodeinfo/system/domain_code");
+		let aggregate = aggregates.entry((site, domain)).or_default();
+		aggregate.nodes += 1;
+
+		if let Some(rates) = response.data.get(RATES_KEY) {
+			for direction in TRAFFIC {
+				if let Some(v) = number(rates, &format!("/{}/bytes", direction)) {
+					byte_rate.add(with_label(&labels, "direction", direction), v);
+				}
+				if let Some(v) = number(rates, &format!("/{}/packets", direction)) {
+					packet_rate.add(with_label(&labels, "direction", direction), v);
+				}
+			}
+		}
+
+		let statistics = match statistics {
+			Some(s) => s,
+			None => continue,
+		};
+
+		for kind in &["total", "wifi24", "wifi5"] {
+			if let Some(v) = number(statistics, &format!("/clients/{}", kind)) {
+				clients.add(with_label(&labels, "type", kind), v);
+			}
+		}
+		if let Some(v) = number(statistics, "/clients/total") {
+			aggregate.clients += v;
+		}
+
+		if let Some(v) = number(statistics, "/loadavg") {
+			load.add(labels.clone(), v);
+		}
+
+		for kind in &["total", "free", "buffers", "cached", "available"] {
+			// respondd reports memory in kB
+			if let Some(v) = number(statistics, &format!("/memory/{}", kind)) {
+				memory.add(with_label(&labels, "type", kind), v * 1024.0);
+			}
+		}
+
+		if let Some(v) = number(statistics, "/uptime") {
+			uptime.add(labels.clone(), v);
+		}
+
+		for direction in TRAFFIC {
+			if let Some(v) = number(statistics, &format!("/traffic/{}/bytes", direction)) {
+				traffic.add(with_label(&labels, "direction", direction), v);
+			}
+			if let Some(v) = number(statistics, &format!("/traffic/{}/packets", direction)) {
+				packets.add(with_label(&labels, "direction", direction), v);
+			}
+		}
+
+		if let Some(v) = number(statistics, "/gateway_tq") {
+			gateway_tq.add(labels.clone(), v);
+		}
+
+		for state in &["total", "running"] {
+			if let Some(v) = number(statistics, &format!("/processes/{}", state)) {
+				processes.add(with_label(&labels, "state", state), v);
+			}
+		}
+	}
+
+	for ((site, domain), aggregate) in &aggregates {
+		let labels = format!("site=\"{}\",domain=\"{}\"", escape(site), escape(domain));
+		sites.add(labels.clone(), aggregate.nodes as f64);
+		site_clients.add(labels, aggregate.clients);
+	}
+
+	let mut out = String::new();
+	for family in &[
+		clients, load, memory, uptime, traffic, packets, byte_rate, packet_rate, gateway_tq, processes, sites,
+		site_clients,
+	] {
+		family.render(&mut out);
+	}
+
+	out
+}
+
+
+/// render the event queue state of all subscribers
+pub fn render_subscribers(subscribers: &[SubscriberStats]) -> String {
+	let mut queued = Family::new("requestd_subscriber_queued_events", "gauge", "events waiting in the subscriber queue");
+	let mut capacity = Family::new("requestd_subscriber_queue_capacity", "gauge", "capacity of the subscriber queue");
+	let mut dropped = Family::new("requestd_subscriber_dropped_events_total", "counter", "events dropped because the subscriber was too slow");
+
+	for subscriber in subscribers {
+		let labels = format!("subscriber=\"{}\"", escape(&subscriber.name));
+		queued.add(labels.clone(), subscriber.queued as f64);
+		capacity.add(labels.clone(), subscriber.capacity as f64);
+		dropped.add(labels, subscriber.dropped as f64);
+	}
+
+	let mut out = String::new();
+	for family in &[queued, capacity, dropped] {
+		family.render(&mut out);
+	}
+
+	out
+}
+
+
+/// render all firing alerts
+pub fn render_alerts(alerts: &[Alert]) -> String {
+	let mut firing = Family::new("requestd_alert_firing", "gauge", "alerts that are currently firing");
+
+	for alert in alerts {
+		firing.add(format!("rule=\"{}\",nodeid=\"{}\"", escape(&alert.rule), escape(&alert.nodeid)), 1.0);
+	}
+
+	let mut out = String::new();
+	firing.render(&mut out);
+	out
+}
+
+
+/// render the status of all expected nodes
+pub fn render_inventory(nodes: &[InventoryNode]) -> String {
+	let mut online = Family::new("requestd_inventory_node_online", "gauge", "expected node is online");
+	let mut status = Family::new("requestd_inventory_node_status", "gauge", "status of the expected node");
+
+	for node in nodes {
+		let labels = format!("nodeid=\"{}\"", escape(&node.nodeid));
+		online.add(labels.clone(), if node.status == Status::Online { 1.0 } else { 0.0 });
+		// every status is always present, so a change doesn't start a new series
+		for s in Status::ALL {
+			status.add(with_label(&labels, "status", s.as_str()), if node.status == s { 1.0 } else { 0.0 });
+		}
+	}
+
+	let mut out = String::new();
+	online.render(&mut out);
+	status.render(&mut out);
+	out
+}
+
+
+/// render the reboot counters of all nodes that rebooted
+pub fn render_reboots(reboots: &HashMap<NodeId, RebootHistory>) -> String {
+	let mut total = Family::new("requestd_node_reboots_total", "counter", "reboots detected by a decreasing uptime");
+
+	let mut nodes: Vec<_> = reboots.iter().collect();
+	nodes.sort_by(|a, b| a.0.cmp(b.0));
+	for (nodeid, history) in nodes {
+		total.add(format!("nodeid=\"{}\"", escape(nodeid)), history.count as f64);
+	}
+
+	let mut out = String::new();
+	total.render(&mut out);
+	out
+}
+
+
+fn node_labels(response: &NodeResponse) -> String {
+	let mut labels = format!("nodeid=\"{}\"", escape(&response.nodeid));
+
+	for (name, pointer) in NODE_LABELS {
+		write!(labels, ",{}=\"{}\"", name, escape(&label_value(&response.data, pointer))).unwrap();
+	}
+
+	labels
+}
+
+fn with_label(labels: &str, name: &str, value: &str) -> String {
+	format!("{},{}=\"{}\"", labels, name, escape(value))
+}
+
+pub fn label_value(data: &json::Value, pointer: &str) -> String {
+	match data.pointer(pointer) {
+		Some(json::Value::String(s)) => s.clone(),
+		Some(json::Value::Null) | None => String::new(),
+		Some(v) => v.to_string(),
+	}
+}
+
+fn number(data: &json::Value, pointer: &str) -> Option<f64> {
+	data.pointer(pointer).and_then(|v| v.as_f64())
+}
+
+/// escape a label value as required by the exposition format
+fn escape(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+
+
+#[test]
+fn label_values_are_escaped() {
+	assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+}
+
+#[test]
+fn render_node_statistics() {
+	let response = NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::Utc::now(),
+		data: json::json!({
+			"nodeinfo": {
+				"hostname": "ffhl-test",
+				"system": {"site_code": "ffhl", "domain_code": "hl"},
+			},
+			"statistics": {
+				"clients": {"total": 3, "wifi24": 1, "wifi5": 2},
+				"loadavg": 0.25,
+			},
+		}),
+	};
+
+	let out = render(&[Arc::new(response)]);
+	assert!(out.contains("# TYPE requestd_node_clients gauge\n"));
+	assert!(out.contains("requestd_node_clients{nodeid=\"c04a00dd692a\",hostname=\"ffhl-test\",site=\"ffhl\",domain=\"hl\",model=\"\",firmware=\"\",type=\"wifi5\"} 2\n"));
+	assert!(out.contains("requestd_node_load{nodeid=\"c04a00dd692a\",hostname=\"ffhl-test\",site=\"ffhl\",domain=\"hl\",model=\"\",firmware=\"\"} 0.25\n"));
+	assert!(out.contains("requestd_site_clients{site=\"ffhl\",domain=\"hl\"} 3\n"));
+	assert!(!out.contains("requestd_node_uptime_seconds"));
+}
@@ -1,14 +1,12 @@
 use paho_mqtt as mqtt;
 use crate::Endpoint;
-use crate::Collector;
 use crate::CONFIG;
-use crossbeam::channel as crossbeam;
-use crate::NodeResponse;
+use crate::collector::{CollectorHandle, EventReceiver};
+use crate::privacy::Redactor;
 use std::thread;
 use std::time::Duration;
 use serde_json as json;
 use log::{error, warn, info, trace};
-use std::sync::{Arc, Mutex};
 
 
 const MQTT_QOS: i32 = 1;
@@ -16,7 +14,8 @@ const RECONNECT_WAIT: u64 = 1000;
 
 pub struct Mqtt {
 	mqtt_client: mqtt::client::Client,
-	events_receiver: crossbeam::Receiver<NodeResponse>,
+	events_receiver: EventReceiver,
+	redactor: Redactor,
 }
 
 
@@ -41,7 +40,7 @@ impl Mqtt {
 
 
 impl Endpoint for Mqtt {
-	fn new(c: Arc<Mutex<Collector>>) -> Self {
+	fn new(c: CollectorHandle) -> Self {
 		let client = mqtt::Client::new(
 			mqtt::CreateOptionsBuilder::new()
 				.server_uri(CONFIG.mqtt.clone().unwrap().broker)
@@ -51,16 +50,21 @@ impl Endpoint for Mqtt {
 		)
 		.expect("error creating mqtt client");
 
+		let conf = CONFIG.mqtt.clone().unwrap();
+		let queue = conf.queue.unwrap_or_else(|| CONFIG.requestd.event_queue.clone());
+
 		Self {
 			mqtt_client: client,
-			events_receiver: c.lock().unwrap().get_events_receiver()
+			events_receiver: c.get_events_receiver("mqtt", queue),
+			redactor: Redactor::new(conf.redact),
 		}
 	}
 
 	fn start(self) -> ! {
 		// self.mqtt_client.is_connected();
 
-		for event in &self.events_receiver {
+		for mut event in &self.events_receiver {
+			self.redactor.apply_event(&mut event);
 			info!("send mqtt event");
 			let msg = mqtt::Message::new(CONFIG.mqtt.clone().unwrap().topic, json::to_string(&event).unwrap(), MQTT_QOS);
 			trace!("sending mqtt message");
@@ -0,0 +1,211 @@
+//! removes personal data from responses before they are published
+use crate::config::Redaction;
+use crate::events::Event;
+use crate::jsonpath;
+use crate::reboots::RebootHistory;
+use crate::NodeResponse;
+use serde_json as json;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+const COORDINATES: &[&str] = &["nodeinfo.location.latitude", "nodeinfo.location.longitude"];
+
+
+/// applies the redaction rules of a single endpoint
+#[derive(Clone, Debug, Default)]
+pub struct Redactor {
+	config: Redaction,
+}
+
+impl Redactor {
+	pub fn new(config: Option<Redaction>) -> Self {
+		Self {
+			config: config.unwrap_or_default(),
+		}
+	}
+
+	/// true if this redactor doesn't change anything
+	pub fn is_noop(&self) -> bool {
+		self.config.drop.is_empty()
+			&& self.config.round_coordinates.is_none()
+			&& !self.config.hash_macs
+			&& !self.config.hash_nodeids
+	}
+
+	pub fn apply(&self, response: &mut NodeResponse) {
+		if self.is_noop() {
+			return;
+		}
+
+		for path in &self.config.drop {
+			jsonpath::remove(&mut response.data, path);
+		}
+
+		if let Some(decimals) = self.config.round_coordinates {
+			let factor = 10f64.powi(decimals as i32);
+			for path in COORDINATES {
+				jsonpath::for_each_mut(&mut response.data, path, &mut |v| {
+					if let Some(n) = v.as_f64() {
+						*v = json::json!((n * factor).round() / factor);
+					}
+				});
+			}
+		}
+
+		if self.config.hash_macs || self.config.hash_nodeids {
+			let data = response.data.take();
+			response.data = self.hash_value(data, None);
+		}
+
+		if self.config.hash_nodeids {
+			response.nodeid = self.hash_nodeid(&response.nodeid);
+		}
+	}
+
+	pub fn apply_event(&self, event: &mut Event) {
+		match event {
+			Event::Response(r) => self.apply(r),
+			Event::Node(e) => {
+				let nodeid = e.nodeid_mut();
+				*nodeid = self.nodeid(nodeid);
+			}
+		}
+	}
+
+	/// the node id as it appears in redacted responses
+	pub fn nodeid(&self, nodeid: &str) -> String {
+		if self.config.hash_nodeids {
+			self.hash_nodeid(nodeid)
+		} else {
+			nodeid.to_string()
+		}
+	}
+
+	pub fn reboot_history(&self, history: &RebootHistory) -> RebootHistory {
+		let mut history = history.clone();
+		for reboot in history.history.iter_mut() {
+			reboot.nodeid = self.nodeid(&reboot.nodeid);
+		}
+		history
+	}
+
+	pub fn redact(&self, responses: &[Arc<NodeResponse>]) -> Vec<Arc<NodeResponse>> {
+		responses
+			.iter()
+			.map(|r| {
+				let mut r = NodeResponse::clone(r);
+				self.apply(&mut r);
+				Arc::new(r)
+			})
+			.collect()
+	}
+
+	/// Walks the whole json and replaces all macs, and the node ids under
+	/// `node_id` or `nodeid`. Node ids anywhere else are left as they are.
+	fn hash_value(&self, value: Value, key: Option<&str>) -> Value {
+		match value {
+			Value::String(s) if self.config.hash_nodeids && matches!(key, Some("node_id" | "nodeid")) => {
+				Value::String(self.hash_nodeid(&s))
+			}
+			Value::String(s) if self.config.hash_macs && is_mac(&s) => Value::String(self.hash_mac(&s)),
+			Value::Array(list) => Value::Array(list.into_iter().map(|v| self.hash_value(v, None)).collect()),
+			Value::Object(map) => Value::Object(
+				map.into_iter()
+					.map(|(k, v)| {
+						let v = self.hash_value(v, Some(&k));
+						let k = if self.config.hash_macs && is_mac(&k) {
+							self.hash_mac(&k)
+						} else {
+							k
+						};
+						(k, v)
+					})
+					.collect(),
+			),
+			v => v,
+		}
+	}
+
+	/// The hash is formatted as locally administered mac,
+	/// so the same mac always maps to the same hash.
+	fn hash_mac(&self, mac: &str) -> String {
+		let mut hasher = Sha256::new();
+		hasher.update(self.config.hash_salt.as_bytes());
+		hasher.update(mac.to_lowercase().as_bytes());
+		let hash = hasher.finalize();
+
+		let mut bytes = [0u8; 6];
+		bytes.copy_from_slice(&hash[..6]);
+		bytes[0] = (bytes[0] | 0x02) & !0x01;
+
+		bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+	}
+
+	/// node ids are usually the primary mac without colons. Hash them the
+	/// same way so they still match the hashed mac.
+	fn hash_nodeid(&self, nodeid: &str) -> String {
+		let as_mac = nodeid
+			.as_bytes()
+			.chunks(2)
+			.map(|c| String::from_utf8_lossy(c).to_string())
+			.collect::<Vec<_>>()
+			.join(":");
+
+		self.hash_mac(&as_mac).replace(':', "")
+	}
+}
+
+
+fn is_mac(s: &str) -> bool {
+	s.len() == 17
+		&& s.split(':').count() == 6
+		&& s.split(':').all(|p| p.len() == 2 && p.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+
+
+#[test]
+fn redact_response() {
+	let redactor = Redactor::new(Some(Redaction {
+		drop: vec!["nodeinfo.owner".to_string()],
+		round_coordinates: Some(2),
+		hash_macs: true,
+		hash_nodeids: true,
+		hash_salt: "salt".to_string(),
+	}));
+
+	let mut response = NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::Utc::now(),
+		data: json::json!({
+			"nodeinfo": {
+				"node_id": "c04a00dd692a",
+				"network": {"mac": "c0:4a:00:dd:69:2a"},
+				"owner": {"contact": "me@example.org"},
+				"location": {"latitude": 53.869_123, "longitude": 10.686_789},
+			},
+			"neighbours": {"batadv": {"c0:4a:00:dd:69:2a": {"neighbours": {}}}},
+			"meta": {"nodeid": "c04a00dd692a"},
+		}),
+	};
+	redactor.apply(&mut response);
+
+	let mac = redactor.hash_mac("c0:4a:00:dd:69:2a");
+	assert_ne!(mac, "c0:4a:00:dd:69:2a");
+	assert!(is_mac(&mac));
+	assert_eq!(response.nodeid, mac.replace(':', ""));
+	assert_eq!(
+		response.data,
+		json::json!({
+			"nodeinfo": {
+				"node_id": mac.replace(':', ""),
+				"network": {"mac": mac},
+				"location": {"latitude": 53.87, "longitude": 10.69},
+			},
+			"neighbours": {"batadv": {mac.clone(): {"neighbours": {}}}},
+			"meta": {"nodeid": mac.replace(':', "")},
+		})
+	);
+}
@@ -0,0 +1,246 @@
+//! every response passes a chain of processors before it is stored and published
+use crate::config::ProcessorConfig;
+use crate::jsonpath;
+use crate::metadata::Metadata;
+use crate::rates::Rates;
+use crate::NodeResponse;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde_json as json;
+use std::collections::BTreeMap;
+
+
+pub enum Verdict {
+	/// pass the (possibly modified) response on to the next processor
+	Keep,
+	/// discard the response. The reason is logged
+	Drop(String),
+}
+
+
+pub trait Processor: Send {
+	fn name(&self) -> &str;
+
+	/// inspect or modify a single response
+	fn process(&mut self, response: &mut NodeResponse) -> Verdict;
+}
+
+
+#[derive(Default)]
+pub struct Pipeline {
+	processors: Vec<Box<dyn Processor>>,
+}
+
+impl Pipeline {
+	/// `interval` is the request interval in seconds
+	pub fn from_config(config: &[ProcessorConfig], interval: u64) -> Self {
+		let mut pipeline = Self::default();
+
+		for conf in config {
+			let processor: Box<dyn Processor> = match conf.clone() {
+				ProcessorConfig::Validate { require } => Box::new(Validate { require }),
+				ProcessorConfig::Enrich { set } => Box::new(Enrich { set }),
+				ProcessorConfig::Filter { path, values } => Box::new(Filter {
+					name: "filter",
+					path,
+					values,
+					keep_matching: true,
+				}),
+				ProcessorConfig::Drop { path, values } => Box::new(Filter {
+					name: "drop",
+					path,
+					values,
+					keep_matching: false,
+				}),
+				ProcessorConfig::Metadata { file, key } => Box::new(Metadata::new(file, key)),
+				ProcessorConfig::Rates { window } => Box::new(Rates::new(window, interval)),
+				ProcessorConfig::Rewrite { remove, rename, set } => Box::new(Rewrite { remove, rename, set }),
+			};
+
+			pipeline.push(processor);
+		}
+
+		pipeline
+	}
+
+	/// append a processor to the end of the chain
+	pub fn push(&mut self, processor: Box<dyn Processor>) {
+		debug!("adding processor: {}", processor.name());
+		self.processors.push(processor);
+	}
+
+	/// returns `None` if a processor dropped the response
+	pub fn process(&mut self, mut response: NodeResponse) -> Option<NodeResponse> {
+		for processor in self.processors.iter_mut() {
+			if let Verdict::Drop(reason) = processor.process(&mut response) {
+				debug!("{} dropped response of {}: {}", processor.name(), response.nodeid, reason);
+				return None;
+			}
+		}
+
+		Some(response)
+	}
+}
+
+
+/// checks that all categories are objects and belong to the same node
+pub struct Validate {
+	require: Vec<String>,
+}
+
+impl Processor for Validate {
+	fn name(&self) -> &str {
+		"validate"
+	}
+
+	fn process(&mut self, response: &mut NodeResponse) -> Verdict {
+		let categories = match response.data.as_object() {
+			Some(c) if !c.is_empty() => c,
+			_ => return Verdict::Drop(format!("invalid data from {}", response.remote)),
+		};
+
+		for (name, category) in categories {
+			if !category.is_object() {
+				return Verdict::Drop(format!("{} is not an object", name));
+			}
+
+			match category.get("node_id").and_then(|n| n.as_str()) {
+				Some(nodeid) if nodeid != response.nodeid => {
+					return Verdict::Drop(format!("{} belongs to node {}", name, nodeid));
+				}
+				_ => (),
+			}
+		}
+
+		for path in &self.require {
+			if jsonpath::get(&response.data, path).is_none() {
+				return Verdict::Drop(format!("{} is missing", path));
+			}
+		}
+
+		Verdict::Keep
+	}
+}
+
+
+/// sets values that are not present yet
+pub struct Enrich {
+	set: BTreeMap<String, json::Value>,
+}
+
+impl Processor for Enrich {
+	fn name(&self) -> &str {
+		"enrich"
+	}
+
+	fn process(&mut self, response: &mut NodeResponse) -> Verdict {
+		for (path, value) in &self.set {
+			if jsonpath::get(&response.data, path).is_none() {
+				jsonpath::set(&mut response.data, path, value.clone());
+			}
+		}
+
+		Verdict::Keep
+	}
+}
+
+
+/// keeps or drops responses depending on a single value
+pub struct Filter {
+	name: &'static str,
+	path: String,
+	values: Vec<json::Value>,
+	/// keep or drop the responses that match
+	keep_matching: bool,
+}
+
+impl Processor for Filter {
+	fn name(&self) -> &str {
+		self.name
+	}
+
+	fn process(&mut self, response: &mut NodeResponse) -> Verdict {
+		let matches = match jsonpath::get(&response.data, &self.path) {
+			Some(value) => self.values.is_empty() || self.values.contains(value),
+			None => false,
+		};
+
+		if matches == self.keep_matching {
+			Verdict::Keep
+		} else {
+			Verdict::Drop(format!("filtered by {}", self.path))
+		}
+	}
+}
+
+
+/// removes, moves and overwrites values
+pub struct Rewrite {
+	remove: Vec<String>,
+	rename: BTreeMap<String, String>,
+	set: BTreeMap<String, json::Value>,
+}
+
+impl Processor for Rewrite {
+	fn name(&self) -> &str {
+		"rewrite"
+	}
+
+	fn process(&mut self, response: &mut NodeResponse) -> Verdict {
+		for path in &self.remove {
+			jsonpath::remove(&mut response.data, path);
+		}
+
+		for (from, to) in &self.rename {
+			if let Some(value) = jsonpath::get(&response.data, from).cloned() {
+				jsonpath::remove(&mut response.data, from);
+				jsonpath::set(&mut response.data, to, value);
+			}
+		}
+
+		for (path, value) in &self.set {
+			jsonpath::set(&mut response.data, path, value.clone());
+		}
+
+		Verdict::Keep
+	}
+}
+
+
+
+#[test]
+fn pipeline_from_config() {
+	let config: Vec<ProcessorConfig> = serde_yaml::from_str(
+		r#"
+- type: validate
+- type: drop
+  path: nodeinfo.hostname
+  values: ["drop-me"]
+- type: rewrite
+  remove: [nodeinfo.owner]
+  set:
+    nodeinfo.tags.community: ffhl
+"#,
+	)
+	.unwrap();
+	let mut pipeline = Pipeline::from_config(&config, 60);
+
+	let response = |hostname: &str| NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::Utc::now(),
+		data: json::json!({
+			"nodeinfo": {"node_id": "c04a00dd692a", "hostname": hostname, "owner": {"contact": "me@example.org"}},
+		}),
+	};
+
+	assert!(pipeline.process(response("drop-me")).is_none());
+
+	let kept = pipeline.process(response("keep-me")).unwrap();
+	assert_eq!(
+		kept.data,
+		json::json!({
+			"nodeinfo": {"node_id": "c04a00dd692a", "hostname": "keep-me", "tags": {"community": "ffhl"}},
+		})
+	);
+}
@@ -0,0 +1,308 @@
+//! filtering, field selection, sorting and pagination of `/responses`
+//!
+//! `/responses?site=ffhl&online=true&fields=nodeinfo.hostname,statistics.clients&sort=-statistics.clients.total&limit=50`
+use crate::jsonpath;
+use crate::metrics::{label_value, NODE_LABELS};
+use crate::{NodeResponse, Timestamp};
+use serde_json as json;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+
+#[derive(Debug, Default)]
+pub struct Query {
+	/// label name -> accepted values
+	labels: HashMap<String, Vec<String>>,
+	online: Option<bool>,
+	/// maximum age in seconds
+	max_age: Option<i64>,
+	fields: Vec<String>,
+	sort: Option<String>,
+	descending: bool,
+	limit: Option<usize>,
+	cursor: Option<(Value, String)>,
+}
+
+
+pub struct Page {
+	pub items: Vec<Value>,
+	/// number of responses that matched the filters
+	pub total: usize,
+	/// pass as `cursor` to get the next page
+	pub next_cursor: Option<String>,
+}
+
+
+impl Query {
+	pub fn parse(query: &str) -> Result<Self, String> {
+		let mut q = Self::default();
+
+		for (key, value) in parse_params(query) {
+			let list = || value.split(',').filter(|v| !v.is_empty()).map(|v| v.to_string()).collect::<Vec<_>>();
+
+			match key.as_str() {
+				k if NODE_LABELS.iter().any(|(name, _)| *name == k) => {
+					q.labels.entry(key.clone()).or_default().extend(list());
+				}
+				"online" => q.online = Some(value.parse().map_err(|_| "online must be true or false")?),
+				"max_age" => q.max_age = Some(value.parse().map_err(|_| "max_age must be a number of seconds")?),
+				"fields" => q.fields.extend(list()),
+				"sort" => {
+					q.descending = value.starts_with('-');
+					q.sort = Some(value.trim_start_matches('-').to_string());
+				}
+				"limit" => q.limit = Some(value.parse().map_err(|_| "limit must be a number")?),
+				"cursor" => q.cursor = Some(decode_cursor(&value).ok_or("invalid cursor")?),
+				_ => return Err(format!("unknown parameter {}", key)),
+			}
+		}
+
+		Ok(q)
+	}
+
+	fn matches(&self, response: &NodeResponse, now: Timestamp) -> bool {
+		for (name, pointer) in NODE_LABELS {
+			if let Some(values) = self.labels.get(*name) {
+				if !values.contains(&label_value(&response.data, pointer)) {
+					return false;
+				}
+			}
+		}
+
+		if let Some(online) = self.online {
+			if response.is_online() != online {
+				return false;
+			}
+		}
+
+		if let Some(max_age) = self.max_age {
+			if (now - response.timestamp).num_seconds() > max_age {
+				return false;
+			}
+		}
+
+		true
+	}
+
+	fn sort_key(&self, response: &NodeResponse) -> Value {
+		match self.sort.as_deref() {
+			None => Value::Null,
+			Some("nodeid") => Value::String(response.nodeid.clone()),
+			Some("timestamp") => json::json!(response.timestamp.timestamp_millis()),
+			Some(path) => jsonpath::get(&response.data, path).cloned().unwrap_or(Value::Null),
+		}
+	}
+
+	/// order of (sort key, node id) pairs. Missing keys go last in both directions.
+	fn compare(&self, a: (&Value, &str), b: (&Value, &str)) -> Ordering {
+		let by_key = match (a.0, b.0) {
+			(Value::Null, Value::Null) => Ordering::Equal,
+			(Value::Null, _) => Ordering::Greater,
+			(_, Value::Null) => Ordering::Less,
+			_ if self.descending => compare_values(a.0, b.0).reverse(),
+			_ => compare_values(a.0, b.0),
+		};
+
+		by_key.then_with(|| a.1.cmp(b.1))
+	}
+
+	fn select(&self, response: &NodeResponse) -> Value {
+		let mut value = json::to_value(response).unwrap();
+		if self.fields.is_empty() {
+			return value;
+		}
+
+		let mut data = json::json!({});
+		for field in &self.fields {
+			if let Some(v) = jsonpath::get(&response.data, field) {
+				jsonpath::set(&mut data, field, v.clone());
+			}
+		}
+		value["data"] = data;
+		value
+	}
+
+	pub fn apply(&self, responses: &[Arc<NodeResponse>], now: Timestamp) -> Page {
+		let mut matching: Vec<(Value, &NodeResponse)> = responses
+			.iter()
+			.filter(|r| self.matches(r, now))
+			.map(|r| (self.sort_key(r), &**r))
+			.collect();
+		matching.sort_by(|a, b| self.compare((&a.0, &a.1.nodeid), (&b.0, &b.1.nodeid)));
+
+		let start = match &self.cursor {
+			Some((key, nodeid)) => matching
+				.iter()
+				.position(|(k, r)| self.compare((k, &r.nodeid), (key, nodeid)) == Ordering::Greater)
+				.unwrap_or(matching.len()),
+			None => 0,
+		};
+		let end = match self.limit {
+			Some(limit) => (start + limit).min(matching.len()),
+			None => matching.len(),
+		};
+
+		let next_cursor = match end {
+			end if end < matching.len() && end > start => {
+				let (key, response) = &matching[end - 1];
+				Some(encode_cursor(key, &response.nodeid))
+			}
+			_ => None,
+		};
+
+		Page {
+			items: matching[start..end].iter().map(|(_, r)| self.select(r)).collect(),
+			total: matching.len(),
+			next_cursor,
+		}
+	}
+}
+
+
+/// numbers before strings before everything else, missing values last
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+	fn rank(v: &Value) -> u8 {
+		match v {
+			Value::Number(_) => 0,
+			Value::String(_) => 1,
+			Value::Bool(_) => 2,
+			Value::Null => 4,
+			_ => 3,
+		}
+	}
+
+	match (a, b) {
+		(Value::Number(x), Value::Number(y)) => {
+			x.as_f64().unwrap_or(0.0).partial_cmp(&y.as_f64().unwrap_or(0.0)).unwrap_or(Ordering::Equal)
+		}
+		(Value::String(x), Value::String(y)) => x.cmp(y),
+		(Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+		_ => rank(a).cmp(&rank(b)).then_with(|| a.to_string().cmp(&b.to_string())),
+	}
+}
+
+
+/// the position after the last item of a page, hex encoded
+fn encode_cursor(key: &Value, nodeid: &str) -> String {
+	json::to_vec(&json::json!([key, nodeid])).unwrap().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Option<(Value, String)> {
+	// an odd length fails at the last chunk
+	let bytes = (0..cursor.len())
+		.step_by(2)
+		.map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+		.collect::<Option<Vec<u8>>>()?;
+
+	let (key, nodeid): (Value, String) = json::from_slice(&bytes).ok()?;
+	Some((key, nodeid))
+}
+
+
+/// split a query string into decoded key value pairs
+pub fn parse_params(query: &str) -> Vec<(String, String)> {
+	query
+		.split('&')
+		.filter(|p| !p.is_empty())
+		.map(|p| match p.find('=') {
+			Some(i) => (decode_param(&p[..i]), decode_param(&p[i + 1..])),
+			None => (decode_param(p), String::new()),
+		})
+		.collect()
+}
+
+/// in a query a `+` is a space
+fn decode_param(s: &str) -> String {
+	percent_decode(&s.replace('+', " "))
+}
+
+pub fn percent_decode(s: &str) -> String {
+	let bytes = s.as_bytes();
+	let mut out = Vec::with_capacity(bytes.len());
+
+	let mut i = 0;
+	while i < bytes.len() {
+		match bytes[i] {
+			b'%' => match s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+				Some(b) => {
+					out.push(b);
+					i += 2;
+				}
+				None => out.push(b'%'),
+			},
+			b => out.push(b),
+		}
+		i += 1;
+	}
+
+	String::from_utf8_lossy(&out).to_string()
+}
+
+
+
+#[test]
+fn filter_sort_and_paginate() {
+	let now = chrono::Utc::now();
+	let response = |nodeid: &str, site: &str, clients: u64| Arc::new(NodeResponse {
+		nodeid: nodeid.to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: now,
+		data: json::json!({
+			"nodeinfo": {"hostname": nodeid, "system": {"site_code": site}},
+			"statistics": {"clients": {"total": clients}},
+		}),
+	});
+	let responses = vec![
+		response("a", "ffhl", 5),
+		response("b", "ffhl", 10),
+		response("c", "ffhl", 1),
+		response("d", "other", 100),
+	];
+
+	let query = Query::parse("site=ffhl&sort=-statistics.clients.total&fields=statistics.clients.total&limit=2").unwrap();
+	let page = query.apply(&responses, now);
+	assert_eq!(page.total, 3);
+	assert_eq!(page.items.len(), 2);
+	assert_eq!(page.items[0]["nodeid"], "b");
+	assert_eq!(page.items[0]["data"], json::json!({"statistics": {"clients": {"total": 10}}}));
+
+	let next = format!("site=ffhl&sort=-statistics.clients.total&limit=2&cursor={}", page.next_cursor.unwrap());
+	let page = Query::parse(&next).unwrap().apply(&responses, now);
+	assert_eq!(page.items.len(), 1);
+	assert_eq!(page.items[0]["nodeid"], "c");
+	assert!(page.next_cursor.is_none());
+
+	assert!(Query::parse("color=red").is_err());
+	assert_eq!(parse_params("a=b%20c+d&e"), vec![("a".into(), "b c d".into()), ("e".into(), "".into())]);
+}
+
+#[test]
+fn sort_by_nodeid_and_missing_values() {
+	let now = chrono::Utc::now();
+	let response = |nodeid: &str, clients: Option<u64>| Arc::new(NodeResponse {
+		nodeid: nodeid.to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: now,
+		data: match clients {
+			Some(c) => json::json!({"statistics": {"clients": {"total": c}}}),
+			None => json::json!({}),
+		},
+	});
+	let responses = vec![response("b", Some(5)), response("a", None), response("c", Some(1))];
+	let order = |query: &str| -> Vec<String> {
+		let page = Query::parse(query).unwrap().apply(&responses, now);
+		page.items.iter().map(|i| i["nodeid"].as_str().unwrap().to_string()).collect()
+	};
+
+	assert_eq!(order("sort=nodeid"), vec!["a", "b", "c"]);
+	assert_eq!(order("sort=-nodeid"), vec!["c", "b", "a"]);
+	assert_eq!(order("sort=statistics.clients.total"), vec!["c", "b", "a"]);
+	assert_eq!(order("sort=-statistics.clients.total"), vec!["b", "c", "a"]);
+
+	// the cursor keeps nodes without a value on the last page
+	let page = Query::parse("sort=-statistics.clients.total&limit=2").unwrap().apply(&responses, now);
+	let next = format!("sort=-statistics.clients.total&limit=2&cursor={}", page.next_cursor.unwrap());
+	assert_eq!(order(&next), vec!["a"]);
+}
@@ -0,0 +1,199 @@
+//! per second rates of the traffic counters
+use crate::processor::{Processor, Verdict};
+use crate::{NodeId, NodeResponse, Timestamp};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde_json as json;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+pub const RATES_KEY: &str = "rates";
+pub const TRAFFIC: &[&str] = &["rx", "tx", "forward", "mgmt_rx", "mgmt_tx"];
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+
+/// counters of a single response
+struct Sample {
+	timestamp: Timestamp,
+	uptime: Option<f64>,
+	/// traffic -> counter -> value
+	counters: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+impl Sample {
+	fn from_response(response: &NodeResponse) -> Option<Self> {
+		let traffic = response.data.pointer("/statistics/traffic")?.as_object()?;
+
+		let mut counters = BTreeMap::new();
+		for name in TRAFFIC {
+			let values: BTreeMap<String, f64> = traffic
+				.get(*name)
+				.and_then(|t| t.as_object())
+				.iter()
+				.flat_map(|t| t.iter())
+				.filter_map(|(k, v)| Some((k.clone(), v.as_f64()?)))
+				.collect();
+
+			if !values.is_empty() {
+				counters.insert(name.to_string(), values);
+			}
+		}
+
+		Some(Self {
+			timestamp: response.timestamp,
+			uptime: response.data.pointer("/statistics/uptime").and_then(|u| u.as_f64()),
+			counters,
+		})
+	}
+
+	/// counters only go down if the node rebooted
+	fn is_reset_since(&self, previous: &Sample) -> bool {
+		if let (Some(now), Some(before)) = (self.uptime, previous.uptime) {
+			if now < before {
+				return true;
+			}
+		}
+
+		self.counters.iter().any(|(name, values)| {
+			values.iter().any(|(k, v)| previous.counters.get(name).and_then(|p| p.get(k)).is_some_and(|p| v < p))
+		})
+	}
+}
+
+
+/// Keeps the previous sample of every node and adds the per second
+/// rates of the traffic counters to the response under `rates`.
+pub struct Rates {
+	previous: HashMap<NodeId, Sample>,
+	/// how long the previous sample is kept
+	window: chrono::Duration,
+	last_prune: Instant,
+}
+
+impl Rates {
+	/// Keeps samples for `window` seconds, but at least for two request
+	/// `interval`s. Otherwise there would never be a previous sample.
+	pub fn new(window: u64, interval: u64) -> Self {
+		Self {
+			previous: HashMap::new(),
+			window: chrono::Duration::seconds(window.max(2 * interval) as i64),
+			last_prune: Instant::now(),
+		}
+	}
+
+	/// forget nodes that are gone
+	fn prune(&mut self, now: Timestamp) {
+		let window = self.window;
+		self.previous.retain(|_, s| now - s.timestamp <= window);
+		self.last_prune = Instant::now();
+	}
+}
+
+impl Processor for Rates {
+	fn name(&self) -> &str {
+		"rates"
+	}
+
+	fn process(&mut self, response: &mut NodeResponse) -> Verdict {
+		if self.last_prune.elapsed() > PRUNE_INTERVAL {
+			self.prune(chrono::Utc::now());
+		}
+
+		let sample = match Sample::from_response(response) {
+			Some(s) => s,
+			None => return Verdict::Keep,
+		};
+
+		let previous = match self.previous.insert(response.nodeid.clone(), sample) {
+			Some(p) => p,
+			None => return Verdict::Keep,
+		};
+		let sample = &self.previous[&response.nodeid];
+
+		let seconds = (sample.timestamp - previous.timestamp).num_milliseconds() as f64 / 1000.0;
+		if seconds <= 0.0 {
+			return Verdict::Keep;
+		}
+
+		if sample.is_reset_since(&previous) {
+			debug!("counters of {} were reset", response.nodeid);
+			return Verdict::Keep;
+		}
+
+		let mut rates = json::Map::new();
+		for (name, values) in &sample.counters {
+			let per_second: json::Map<String, json::Value> = values
+				.iter()
+				.filter_map(|(k, v)| {
+					let before = previous.counters.get(name)?.get(k)?;
+					Some((k.clone(), json::json!((v - before) / seconds)))
+				})
+				.collect();
+
+			if !per_second.is_empty() {
+				rates.insert(name.clone(), json::Value::Object(per_second));
+			}
+		}
+		rates.insert("interval".to_string(), json::json!(seconds));
+
+		if let Some(data) = response.data.as_object_mut() {
+			data.insert(RATES_KEY.to_string(), json::Value::Object(rates));
+		}
+
+		Verdict::Keep
+	}
+}
+
+
+
+#[test]
+fn rates_and_counter_resets() {
+	let response = |seconds: i64, uptime: u64, rx: u64| NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::TimeZone::timestamp_opt(&chrono::Utc, 1_600_000_000 + seconds, 0).unwrap(),
+		data: json::json!({
+			"statistics": {"uptime": uptime, "traffic": {"rx": {"bytes": rx, "packets": rx / 100}}},
+		}),
+	};
+
+	let mut rates = Rates::new(0, 10);
+	let mut first = response(0, 1000, 10_000);
+	rates.process(&mut first);
+	assert!(first.data.get(RATES_KEY).is_none());
+
+	let mut second = response(10, 1010, 20_000);
+	rates.process(&mut second);
+	assert_eq!(
+		second.data[RATES_KEY],
+		json::json!({"rx": {"bytes": 1000.0, "packets": 10.0}, "interval": 10.0})
+	);
+
+	// the node rebooted
+	let mut third = response(20, 5, 500);
+	rates.process(&mut third);
+	assert!(third.data.get(RATES_KEY).is_none());
+}
+
+#[test]
+fn window_covers_the_interval() {
+	let response = |seconds: i64| NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::TimeZone::timestamp_opt(&chrono::Utc, 1_600_000_000 + seconds, 0).unwrap(),
+		data: json::json!({"statistics": {"traffic": {"rx": {"bytes": seconds * 100}}}}),
+	};
+
+	// a window shorter than the interval is raised to two intervals
+	let mut rates = Rates::new(10, 60);
+	rates.process(&mut response(0));
+	rates.prune(response(60).timestamp);
+
+	let mut next = response(60);
+	rates.process(&mut next);
+	assert_eq!(next.data[RATES_KEY]["rx"]["bytes"], 100.0);
+
+	rates.prune(response(60 + 121).timestamp);
+	assert!(rates.previous.is_empty());
+}
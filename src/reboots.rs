@@ -0,0 +1,144 @@
+//! notices nodes whose uptime went backwards
+use crate::{NodeId, NodeResponse, Timestamp};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+/// Nodes are forgotten after not responding for this many times `offline_after`.
+/// Until then a node that comes back is still recognized as rebooted.
+const FORGET_AFTER_OFFLINE: i64 = 20;
+
+
+/// a single reboot as seen between two responses
+#[derive(Clone, Debug, Serialize)]
+pub struct Reboot {
+	pub nodeid: NodeId,
+	pub hostname: Option<String>,
+	/// time of the first response after the reboot
+	pub timestamp: Timestamp,
+	pub old_uptime: f64,
+	pub new_uptime: f64,
+	/// firmware release before the reboot
+	pub old_firmware: Option<String>,
+	pub new_firmware: Option<String>,
+}
+
+
+/// reboots of a single node
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RebootHistory {
+	/// all reboots since the node was first seen
+	pub count: u64,
+	/// the most recent reboots, oldest first
+	pub history: VecDeque<Reboot>,
+	#[serde(skip)]
+	uptime: Option<f64>,
+	#[serde(skip)]
+	firmware: Option<String>,
+	#[serde(skip)]
+	last_seen: Option<Timestamp>,
+}
+
+
+pub struct RebootTracker {
+	nodes: HashMap<NodeId, RebootHistory>,
+	/// number of reboots kept per node
+	max_history: usize,
+	/// seconds
+	offline_after: u64,
+}
+
+impl RebootTracker {
+	pub fn new(max_history: usize, offline_after: u64) -> Self {
+		Self {
+			nodes: HashMap::new(),
+			max_history,
+			offline_after,
+		}
+	}
+
+	/// Returns the reboot if the uptime of the node decreased since its last response.
+	pub fn observe(&mut self, response: &NodeResponse) -> Option<Reboot> {
+		let uptime = response.data.pointer("/statistics/uptime").and_then(|u| u.as_f64())?;
+		let firmware = response
+			.data
+			.pointer("/nodeinfo/software/firmware/release")
+			.and_then(|f| f.as_str())
+			.map(|f| f.to_string());
+
+		let node = self.nodes.entry(response.nodeid.clone()).or_default();
+		node.last_seen = Some(response.timestamp);
+		let previous = node.uptime.replace(uptime);
+		let old_firmware = match firmware {
+			// responses without nodeinfo keep the last known firmware
+			Some(f) => node.firmware.replace(f),
+			None => node.firmware.clone(),
+		};
+
+		match previous {
+			Some(old_uptime) if uptime < old_uptime => {
+				let reboot = Reboot {
+					nodeid: response.nodeid.clone(),
+					hostname: response.data.pointer("/nodeinfo/hostname").and_then(|h| h.as_str()).map(|h| h.to_string()),
+					timestamp: response.timestamp,
+					old_uptime,
+					new_uptime: uptime,
+					old_firmware,
+					new_firmware: node.firmware.clone(),
+				};
+				debug!("{} rebooted after {}s", reboot.nodeid, old_uptime);
+
+				node.count += 1;
+				node.history.push_back(reboot.clone());
+				while node.history.len() > self.max_history {
+					node.history.pop_front();
+				}
+
+				Some(reboot)
+			}
+			_ => None,
+		}
+	}
+
+	/// Forget nodes that didn't respond for a long time.
+	/// Independent of the response buffer, which drops nodes long before.
+	pub fn expire(&mut self, now: Timestamp) {
+		let max_age = self.offline_after as i64 * FORGET_AFTER_OFFLINE;
+		self.nodes.retain(|_, n| n.last_seen.is_some_and(|t| (now - t).num_seconds() <= max_age));
+	}
+
+	/// nodes that rebooted at least once
+	pub fn rebooted(&self) -> HashMap<NodeId, RebootHistory> {
+		self.nodes.iter().filter(|(_, n)| n.count > 0).map(|(id, n)| (id.clone(), n.clone())).collect()
+	}
+}
+
+
+
+#[test]
+fn uptime_regression_is_a_reboot() {
+	let response = |uptime: f64, firmware: &str| NodeResponse {
+		nodeid: "c04a00dd692a".to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: chrono::Utc::now(),
+		data: serde_json::json!({
+			"nodeinfo": {"software": {"firmware": {"release": firmware}}},
+			"statistics": {"uptime": uptime},
+		}),
+	};
+
+	let mut tracker = RebootTracker::new(1, 180);
+	assert!(tracker.observe(&response(100.0, "v2023.1")).is_none());
+	assert!(tracker.observe(&response(160.0, "v2023.1")).is_none());
+
+	let reboot = tracker.observe(&response(20.0, "v2023.2")).unwrap();
+	assert_eq!(reboot.old_uptime, 160.0);
+	assert_eq!(reboot.old_firmware.as_deref(), Some("v2023.1"));
+	assert_eq!(reboot.new_firmware.as_deref(), Some("v2023.2"));
+
+	tracker.observe(&response(10.0, "v2023.2")).unwrap();
+	let history = &tracker.rebooted()["c04a00dd692a"];
+	assert_eq!(history.count, 2);
+	assert_eq!(history.history.len(), 1);
+}
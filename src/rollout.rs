@@ -0,0 +1,248 @@
+//! breakdown of firmware and autoupdater settings to follow a rollout
+use crate::collector::Snapshot;
+use crate::meshviewer::format_time;
+use crate::topology::escape_xml;
+use crate::{NodeId, NodeResponse};
+use serde::Serialize;
+use serde_json as json;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// shown for nodes that don't report a value
+const UNKNOWN: &str = "unknown";
+
+/// (name, json pointer) of every breakdown
+const BREAKDOWNS: &[(&str, &str)] = &[
+	("firmware", "/nodeinfo/software/firmware/release"),
+	("autoupdater_branch", "/nodeinfo/software/autoupdater/branch"),
+	("autoupdater_enabled", "/nodeinfo/software/autoupdater/enabled"),
+	("model", "/nodeinfo/hardware/model"),
+	("site", "/nodeinfo/system/site_code"),
+];
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Share {
+	pub value: String,
+	pub count: usize,
+	/// of all nodes, 0 to 100
+	pub percent: f64,
+}
+
+
+/// a node that runs an older release than the target
+#[derive(Clone, Debug, Serialize)]
+pub struct LaggingNode {
+	pub nodeid: NodeId,
+	pub hostname: Option<String>,
+	pub firmware: Option<String>,
+	pub autoupdater_branch: Option<String>,
+	pub autoupdater_enabled: Option<bool>,
+	pub site: Option<String>,
+	pub online: bool,
+}
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+	pub timestamp: String,
+	pub nodes: usize,
+	pub target: Option<String>,
+	/// nodes that run the target release or a newer one
+	pub up_to_date: usize,
+	/// breakdown name -> shares, largest first
+	pub breakdowns: BTreeMap<&'static str, Vec<Share>>,
+	pub lagging: Vec<LaggingNode>,
+}
+
+impl Report {
+	pub fn new(snapshot: &Snapshot, target: Option<&str>) -> Self {
+		let responses = snapshot.responses();
+
+		let breakdowns = BREAKDOWNS.iter().map(|(name, pointer)| (*name, breakdown(responses, pointer))).collect();
+
+		let mut lagging: Vec<LaggingNode> = match target {
+			Some(target) => responses
+				.iter()
+				.filter(|r| {
+					match text(r, "/nodeinfo/software/firmware/release") {
+						Some(f) => compare_versions(&f, target) == Ordering::Less,
+						None => true,
+					}
+				})
+				.map(|r| lagging_node(r))
+				.collect(),
+			None => vec![],
+		};
+		lagging.sort_by(|a, b| (&a.firmware, &a.hostname).cmp(&(&b.firmware, &b.hostname)));
+
+		Self {
+			timestamp: format_time(snapshot.created()),
+			nodes: responses.len(),
+			target: target.map(|t| t.to_string()),
+			up_to_date: if target.is_some() { responses.len() - lagging.len() } else { 0 },
+			breakdowns,
+			lagging,
+		}
+	}
+
+	pub fn to_json(&self) -> Vec<u8> {
+		json::to_vec(self).unwrap()
+	}
+
+	pub fn to_html(&self) -> Vec<u8> {
+		let mut out = String::from(concat!(
+			"<html>\n<head>\n<title>requestd rollout</title>\n",
+			"<style>table { border-collapse: collapse; margin-bottom: 1em; } ",
+			"td, th { border: 1px solid #ccc; padding: 2px 8px; text-align: left; }</style>\n",
+			"</head>\n<body>\n<h1>Rollout</h1>\n",
+		));
+
+		writeln!(out, "<p>{} nodes at {}</p>", self.nodes, escape_xml(&self.timestamp)).unwrap();
+		if let Some(target) = &self.target {
+			writeln!(
+				out,
+				"<p>target release <b>{}</b>: {} up to date ({:.1}%), {} lagging</p>",
+				escape_xml(target),
+				self.up_to_date,
+				percent(self.up_to_date, self.nodes),
+				self.lagging.len()
+			)
+			.unwrap();
+		}
+
+		for (name, shares) in &self.breakdowns {
+			writeln!(out, "<h2>{}</h2>\n<table>\n<tr><th>value</th><th>nodes</th><th>%</th></tr>", name).unwrap();
+			for share in shares {
+				writeln!(
+					out,
+					"<tr><td>{}</td><td>{}</td><td>{:.1}</td></tr>",
+					escape_xml(&share.value),
+					share.count,
+					share.percent
+				)
+				.unwrap();
+			}
+			out.push_str("</table>\n");
+		}
+
+		if self.target.is_some() {
+			out.push_str("<h2>lagging nodes</h2>\n<table>\n");
+			out.push_str("<tr><th>node</th><th>hostname</th><th>firmware</th><th>branch</th><th>autoupdater</th><th>site</th><th>online</th></tr>\n");
+			for node in &self.lagging {
+				let cell = |v: &Option<String>| escape_xml(v.as_deref().unwrap_or(UNKNOWN));
+				writeln!(
+					out,
+					"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+					escape_xml(&node.nodeid),
+					cell(&node.hostname),
+					cell(&node.firmware),
+					cell(&node.autoupdater_branch),
+					node.autoupdater_enabled.map_or(UNKNOWN.to_string(), |e| e.to_string()),
+					cell(&node.site),
+					node.online
+				)
+				.unwrap();
+			}
+			out.push_str("</table>\n");
+		}
+
+		out.push_str("</body>\n</html>\n");
+		out.into_bytes()
+	}
+}
+
+
+fn text(response: &NodeResponse, pointer: &str) -> Option<String> {
+	match response.data.pointer(pointer)? {
+		json::Value::String(s) => Some(s.clone()),
+		json::Value::Null => None,
+		v => Some(v.to_string()),
+	}
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+	if total == 0 {
+		0.0
+	} else {
+		count as f64 * 100.0 / total as f64
+	}
+}
+
+fn breakdown(responses: &[Arc<NodeResponse>], pointer: &str) -> Vec<Share> {
+	let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+	for response in responses {
+		*counts.entry(text(response, pointer).unwrap_or_else(|| UNKNOWN.to_string())).or_default() += 1;
+	}
+
+	let mut shares: Vec<Share> = counts
+		.into_iter()
+		.map(|(value, count)| Share {
+			value,
+			count,
+			percent: percent(count, responses.len()),
+		})
+		.collect();
+	shares.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+	shares
+}
+
+fn lagging_node(response: &NodeResponse) -> LaggingNode {
+	LaggingNode {
+		nodeid: response.nodeid.clone(),
+		hostname: text(response, "/nodeinfo/hostname"),
+		firmware: text(response, "/nodeinfo/software/firmware/release"),
+		autoupdater_branch: text(response, "/nodeinfo/software/autoupdater/branch"),
+		autoupdater_enabled: response.data.pointer("/nodeinfo/software/autoupdater/enabled").and_then(|e| e.as_bool()),
+		site: text(response, "/nodeinfo/system/site_code"),
+		online: response.is_online(),
+	}
+}
+
+/// Compares releases like `v2023.1.2` or `1.4+2021-01-02` part by part.
+/// Numbers are compared by value, everything else as text.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+	let parts = |s: &str| -> Vec<String> {
+		let mut parts: Vec<String> = vec![];
+		let mut last_digit = None;
+		for c in s.chars() {
+			let digit = c.is_ascii_digit();
+			if !c.is_alphanumeric() {
+				last_digit = None;
+				continue;
+			}
+			match parts.last_mut() {
+				Some(p) if last_digit == Some(digit) => p.push(c),
+				_ => parts.push(c.to_string()),
+			}
+			last_digit = Some(digit);
+		}
+		parts
+	};
+
+	let (a, b) = (parts(a), parts(b));
+	for (x, y) in a.iter().zip(b.iter()) {
+		let ord = match (x.parse::<u64>(), y.parse::<u64>()) {
+			(Ok(x), Ok(y)) => x.cmp(&y),
+			_ => x.cmp(y),
+		};
+		if ord != Ordering::Equal {
+			return ord;
+		}
+	}
+
+	a.len().cmp(&b.len())
+}
+
+
+
+#[test]
+fn versions_are_compared_numerically() {
+	assert_eq!(compare_versions("v2023.1.10", "v2023.1.9"), Ordering::Greater);
+	assert_eq!(compare_versions("v2022.1", "v2023.1"), Ordering::Less);
+	assert_eq!(compare_versions("v2023.1", "v2023.1"), Ordering::Equal);
+	assert_eq!(compare_versions("v2023.1", "v2023.1.1"), Ordering::Less);
+	assert_eq!(compare_versions("1.4+2021-01-02", "1.4+2021-01-10"), Ordering::Less);
+}
@@ -0,0 +1,298 @@
+//! live stream of all events as server-sent events
+//! https://html.spec.whatwg.org/multipage/server-sent-events.html
+use crate::collector::EventReceiver;
+use crate::events::Event;
+use crate::privacy::Redactor;
+use crate::query::parse_params;
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender, TrySendError};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Deserialize;
+use serde_json as json;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tiny_http::Request;
+
+/// events waiting to be written to a single client
+const CLIENT_QUEUE: usize = 256;
+/// keeps proxies from closing idle connections
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+
+pub(crate) type Numbered = (u64, Arc<Event>);
+
+
+/// Numbers every event and distributes it to the connected clients.
+/// The last events are kept, so clients can resume with `Last-Event-ID`.
+#[derive(Clone)]
+pub struct Hub {
+	state: Arc<Mutex<HubState>>,
+}
+
+struct HubState {
+	next_id: u64,
+	replay: VecDeque<Numbered>,
+	capacity: usize,
+	clients: Vec<Sender<Numbered>>,
+}
+
+impl Hub {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			state: Arc::new(Mutex::new(HubState {
+				next_id: 1,
+				replay: VecDeque::with_capacity(capacity),
+				capacity,
+				clients: vec![],
+			})),
+		}
+	}
+
+	/// publish everything from `events` on a separate thread
+	pub fn start(capacity: usize, events: EventReceiver) -> Self {
+		let hub = Self::new(capacity);
+
+		let h = hub.clone();
+		thread::spawn(move || {
+			for event in &events {
+				h.publish(event);
+			}
+		});
+
+		hub
+	}
+
+	pub fn publish(&self, event: Event) {
+		let mut state = self.state.lock().unwrap();
+
+		let numbered = (state.next_id, Arc::new(event));
+		state.next_id += 1;
+
+		if state.replay.len() >= state.capacity {
+			state.replay.pop_front();
+		}
+		if state.capacity > 0 {
+			state.replay.push_back(numbered.clone());
+		}
+
+		state.clients.retain(|c| match c.try_send(numbered.clone()) {
+			Ok(()) => true,
+			Err(TrySendError::Full(_)) => {
+				trace!("sse client is too slow. dropping event {}", numbered.0);
+				true
+			}
+			Err(TrySendError::Disconnected(_)) => false,
+		});
+	}
+
+	/// the buffered events after `last_id` and a receiver for all new ones
+	pub(crate) fn subscribe(&self, last_id: Option<u64>) -> (Vec<Numbered>, Receiver<Numbered>) {
+		let mut state = self.state.lock().unwrap();
+
+		let replay = match last_id {
+			Some(last) => state.replay.iter().filter(|(id, _)| *id > last).cloned().collect(),
+			None => vec![],
+		};
+
+		let (tx, rx) = channel::bounded(CLIENT_QUEUE);
+		state.clients.push(tx);
+		debug!("new sse client. {} connected", state.clients.len());
+
+		(replay, rx)
+	}
+}
+
+
+/// `?nodeid=...&category=...&type=...` with comma separated values
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Filter {
+	#[serde(rename = "nodeid")]
+	nodeids: Vec<String>,
+	/// only these categories of responses
+	#[serde(rename = "category")]
+	categories: Vec<String>,
+	/// `response`, `rebooted`, `alert` or `inventory`
+	#[serde(rename = "type")]
+	kinds: Vec<String>,
+}
+
+impl Filter {
+	fn parse(query: &str) -> Self {
+		let mut filter = Self::default();
+
+		for (key, value) in parse_params(query) {
+			let values = value.split(',').filter(|v| !v.is_empty()).map(|v| v.to_string());
+			match key.as_str() {
+				"nodeid" => filter.nodeids.extend(values),
+				"category" => filter.categories.extend(values),
+				"type" => filter.kinds.extend(values),
+				_ => (),
+			}
+		}
+
+		filter
+	}
+
+	/// the event as the client should see it, if at all
+	pub(crate) fn apply(&self, event: &Event) -> Option<Event> {
+		if !self.kinds.is_empty() && !self.kinds.iter().any(|k| k == event.kind()) {
+			return None;
+		}
+		if !self.nodeids.is_empty() && !self.nodeids.iter().any(|n| n == event.nodeid()) {
+			return None;
+		}
+
+		let mut event = event.clone();
+		if let (Event::Response(response), false) = (&mut event, self.categories.is_empty()) {
+			let data = response.data.as_object_mut()?;
+			data.retain(|category, _| self.categories.contains(category));
+			if data.is_empty() {
+				return None;
+			}
+		}
+
+		Some(event)
+	}
+}
+
+
+fn format_event(id: u64, event: &Event) -> String {
+	format!("id: {}\nevent: {}\ndata: {}\n\n", id, event.kind(), json::to_string(event).unwrap())
+}
+
+
+/// streams the events on a new thread until the client disconnects
+pub fn handle(req: Request, hub: &Hub, redactor: Arc<Redactor>) {
+	let query = req.url().split_once('?').map(|(_, q)| q.to_string()).unwrap_or_default();
+	let filter = Filter::parse(&query);
+
+	let last_id = req
+		.headers()
+		.iter()
+		.find(|h| h.field.equiv("Last-Event-ID"))
+		.and_then(|h| h.value.as_str().trim().parse::<u64>().ok());
+	let (replay, events) = hub.subscribe(last_id);
+
+	thread::spawn(move || {
+		let mut writer = req.into_writer();
+		let headers = concat!(
+			"HTTP/1.1 200 OK\r\n",
+			"Content-Type: text/event-stream\r\n",
+			"Cache-Control: no-cache\r\n",
+			"Connection: keep-alive\r\n",
+			"\r\n",
+		);
+		// tell the browser how long to wait before reconnecting
+		if writer.write_all(headers.as_bytes()).and_then(|_| writer.write_all(b"retry: 3000\n\n")).is_err() {
+			return;
+		}
+
+		let send = |writer: &mut Box<dyn Write + Send>, (id, event): Numbered| -> std::io::Result<()> {
+			let mut event = (*event).clone();
+			redactor.apply_event(&mut event);
+			match filter.apply(&event) {
+				Some(e) => writer.write_all(format_event(id, &e).as_bytes()),
+				None => Ok(()),
+			}
+		};
+
+		for numbered in replay {
+			if send(&mut writer, numbered).is_err() {
+				return;
+			}
+		}
+		// the writer is buffered, and there may be no new event for a while
+		if writer.flush().is_err() {
+			return;
+		}
+
+		loop {
+			let result = match events.recv_timeout(KEEPALIVE) {
+				Ok(numbered) => send(&mut writer, numbered),
+				Err(RecvTimeoutError::Timeout) => writer.write_all(b": keepalive\n\n"),
+				Err(RecvTimeoutError::Disconnected) => return,
+			};
+
+			if result.and_then(|_| writer.flush()).is_err() {
+				debug!("sse client disconnected");
+				return;
+			}
+		}
+	});
+}
+
+
+
+#[test]
+fn replay_and_filter() {
+	let response = |nodeid: &str| {
+		Event::Response(crate::NodeResponse {
+			nodeid: nodeid.to_string(),
+			remote: "fe80::1".parse().unwrap(),
+			timestamp: chrono::Utc::now(),
+			data: json::json!({"nodeinfo": {}, "statistics": {}}),
+		})
+	};
+
+	let hub = Hub::new(2);
+	for nodeid in &["a", "b", "c"] {
+		hub.publish(response(nodeid));
+	}
+
+	// only the last two events are kept
+	let (replay, _) = hub.subscribe(Some(0));
+	assert_eq!(replay.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![2, 3]);
+	let (replay, events) = hub.subscribe(Some(3));
+	assert!(replay.is_empty());
+
+	hub.publish(response("d"));
+	let (id, event) = events.try_recv().unwrap();
+	assert_eq!(id, 4);
+
+	let filter = Filter::parse("nodeid=d&category=statistics");
+	match filter.apply(&event) {
+		Some(Event::Response(r)) => assert_eq!(r.data, json::json!({"statistics": {}})),
+		_ => panic!("event was filtered"),
+	}
+	assert!(Filter::parse("nodeid=a").apply(&event).is_none());
+	assert!(Filter::parse("type=alert").apply(&event).is_none());
+}
+
+#[test]
+fn replay_without_new_events() {
+	use std::io::Read;
+
+	let hub = Hub::new(8);
+	for nodeid in &["a", "b", "c"] {
+		hub.publish(Event::Response(crate::NodeResponse {
+			nodeid: nodeid.to_string(),
+			remote: "fe80::1".parse().unwrap(),
+			timestamp: chrono::Utc::now(),
+			data: json::json!({}),
+		}));
+	}
+
+	let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+	let mut client = std::net::TcpStream::connect(server.server_addr()).unwrap();
+	client.write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\nLast-Event-ID: 1\r\n\r\n").unwrap();
+	handle(server.recv().unwrap(), &hub, Arc::new(Redactor::default()));
+
+	// well within the keepalive interval
+	client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	let mut received = String::new();
+	let mut buf = [0; 4096];
+	while !received.contains("id: 3\n") {
+		let n = client.read(&mut buf).expect("replay was not flushed");
+		assert!(n > 0, "connection closed");
+		received.push_str(&String::from_utf8_lossy(&buf[..n]));
+	}
+
+	assert!(received.starts_with("HTTP/1.1 200 OK\r\n"));
+	assert!(received.contains("retry: 3000\n"));
+	assert!(!received.contains("id: 1\n"));
+	assert!(received.contains("id: 2\n"));
+}
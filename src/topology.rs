@@ -0,0 +1,231 @@
+//! the mesh as a graph of nodes and batman-adv links
+use crate::mesh::{self, Link, MacIndex};
+use crate::{NodeId, NodeResponse};
+use serde::Serialize;
+use serde_json as json;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::sync::Arc;
+
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TopologyNode {
+	pub id: NodeId,
+	pub hostname: Option<String>,
+	pub online: bool,
+}
+
+
+/// Built from a snapshot, so it changes as soon as nodes come and go.
+#[derive(Clone, Debug)]
+pub struct Topology {
+	pub nodes: Vec<TopologyNode>,
+	/// only links between online nodes
+	pub links: Vec<Link>,
+	pub index: MacIndex,
+}
+
+impl Topology {
+	/// nodes are online if they responded within `offline_after` seconds
+	pub fn new(responses: &[Arc<NodeResponse>], offline_after: u64) -> Self {
+		let index = MacIndex::new(responses);
+
+		let nodes: Vec<TopologyNode> = responses
+			.iter()
+			.map(|r| TopologyNode {
+				id: r.nodeid.clone(),
+				hostname: r.data.pointer("/nodeinfo/hostname").and_then(|h| h.as_str()).map(|h| h.to_string()),
+				online: r.responded_within(offline_after),
+			})
+			.collect();
+
+		let online: HashSet<&str> = nodes.iter().filter(|n| n.online).map(|n| n.id.as_str()).collect();
+		let links = mesh::links(&mesh::neighbours(responses, &index))
+			.into_iter()
+			.filter(|l| online.contains(l.source.as_str()) && online.contains(l.target.as_str()))
+			.collect();
+
+		Self {
+			nodes,
+			links,
+			index,
+		}
+	}
+
+	pub fn to_json(&self) -> Vec<u8> {
+		let links: Vec<json::Value> = self
+			.links
+			.iter()
+			.map(|l| {
+				json::json!({
+					"source": l.source,
+					"target": l.target,
+					"source_addr": l.source_addr,
+					"target_addr": l.target_addr,
+					"type": l.kind,
+					"source_tq": l.source_tq,
+					"target_tq": l.target_tq,
+					"bidirectional": is_bidirectional(l),
+				})
+			})
+			.collect();
+
+		json::to_vec(&json::json!({
+			"nodes": self.nodes,
+			"links": links,
+		}))
+		.unwrap()
+	}
+
+	/// graphviz
+	pub fn to_dot(&self) -> Vec<u8> {
+		let mut out = String::from("digraph mesh {\n");
+
+		for node in &self.nodes {
+			writeln!(
+				out,
+				"\t\"{}\" [label=\"{}\", color=\"{}\"];",
+				escape_dot(&node.id),
+				escape_dot(node.hostname.as_deref().unwrap_or(&node.id)),
+				if node.online { "green" } else { "red" },
+			)
+			.unwrap();
+		}
+
+		for link in &self.links {
+			writeln!(
+				out,
+				"\t\"{}\" -> \"{}\" [label=\"{}\", type=\"{}\", dir=\"{}\"];",
+				escape_dot(&link.source),
+				escape_dot(&link.target),
+				format_tq(link),
+				link.kind.as_str(),
+				if is_bidirectional(link) { "both" } else { "forward" },
+			)
+			.unwrap();
+		}
+
+		out.push_str("}\n");
+		out.into_bytes()
+	}
+
+	pub fn to_graphml(&self) -> Vec<u8> {
+		let mut out = String::from(concat!(
+			"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+			"<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+			"\t<key id=\"hostname\" for=\"node\" attr.name=\"hostname\" attr.type=\"string\"/>\n",
+			"\t<key id=\"online\" for=\"node\" attr.name=\"online\" attr.type=\"boolean\"/>\n",
+			"\t<key id=\"type\" for=\"edge\" attr.name=\"type\" attr.type=\"string\"/>\n",
+			"\t<key id=\"source_tq\" for=\"edge\" attr.name=\"source_tq\" attr.type=\"double\"/>\n",
+			"\t<key id=\"target_tq\" for=\"edge\" attr.name=\"target_tq\" attr.type=\"double\"/>\n",
+			"\t<key id=\"bidirectional\" for=\"edge\" attr.name=\"bidirectional\" attr.type=\"boolean\"/>\n",
+			"\t<graph id=\"mesh\" edgedefault=\"directed\">\n",
+		));
+
+		for node in &self.nodes {
+			writeln!(out, "\t\t<node id=\"{}\">", escape_xml(&node.id)).unwrap();
+			if let Some(hostname) = &node.hostname {
+				writeln!(out, "\t\t\t<data key=\"hostname\">{}</data>", escape_xml(hostname)).unwrap();
+			}
+			writeln!(out, "\t\t\t<data key=\"online\">{}</data>", node.online).unwrap();
+			out.push_str("\t\t</node>\n");
+		}
+
+		for link in &self.links {
+			writeln!(
+				out,
+				"\t\t<edge source=\"{}\" target=\"{}\">",
+				escape_xml(&link.source),
+				escape_xml(&link.target)
+			)
+			.unwrap();
+			writeln!(out, "\t\t\t<data key=\"type\">{}</data>", link.kind.as_str()).unwrap();
+			if let Some(tq) = link.source_tq {
+				writeln!(out, "\t\t\t<data key=\"source_tq\">{}</data>", tq).unwrap();
+			}
+			if let Some(tq) = link.target_tq {
+				writeln!(out, "\t\t\t<data key=\"target_tq\">{}</data>", tq).unwrap();
+			}
+			writeln!(out, "\t\t\t<data key=\"bidirectional\">{}</data>", is_bidirectional(link)).unwrap();
+			out.push_str("\t\t</edge>\n");
+		}
+
+		out.push_str("\t</graph>\n</graphml>\n");
+		out.into_bytes()
+	}
+}
+
+
+fn is_bidirectional(link: &Link) -> bool {
+	link.source_tq.is_some() && link.target_tq.is_some()
+}
+
+fn format_tq(link: &Link) -> String {
+	let tq = |t: Option<f64>| t.map_or("-".to_string(), |t| format!("{:.2}", t));
+
+	if is_bidirectional(link) {
+		format!("{} / {}", tq(link.source_tq), tq(link.target_tq))
+	} else {
+		tq(link.source_tq)
+	}
+}
+
+fn escape_dot(s: &str) -> String {
+	s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn escape_xml(s: &str) -> String {
+	s.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+		.replace('\'', "&apos;")
+}
+
+
+
+#[test]
+fn topology_formats() {
+	let node = |nodeid: &str, hostname: &str, age: i64, neighbour: &str, tq: u64| {
+		let mac = format!("02:00:00:00:00:{}", nodeid);
+		Arc::new(NodeResponse {
+			nodeid: nodeid.to_string(),
+			remote: "fe80::1".parse().unwrap(),
+			timestamp: chrono::Utc::now() - chrono::Duration::seconds(age),
+			data: json::json!({
+				"nodeinfo": {"hostname": hostname, "network": {"mesh": {"bat0": {"interfaces": {"wireless": [mac]}}}}},
+				"neighbours": {"batadv": {mac: {"neighbours": {format!("02:00:00:00:00:{}", neighbour): {"tq": tq}}}}},
+			}),
+		})
+	};
+	let responses = vec![
+		node("aa", "say \"hi\" <&>", 0, "bb", 255),
+		node("bb", "bb", 0, "aa", 128),
+		// offline, so its link to aa is left out
+		node("cc", "cc", 500, "aa", 255),
+	];
+	let topology = Topology::new(&responses, 180);
+
+	let parsed: json::Value = json::from_slice(&topology.to_json()).unwrap();
+	assert_eq!(parsed["nodes"][0], json::json!({"id": "aa", "hostname": "say \"hi\" <&>", "online": true}));
+	assert_eq!(parsed["nodes"][2]["online"], false);
+	assert_eq!(parsed["links"].as_array().unwrap().len(), 1);
+	assert_eq!(parsed["links"][0]["source"], "aa");
+	assert_eq!(parsed["links"][0]["target"], "bb");
+	assert_eq!(parsed["links"][0]["bidirectional"], true);
+
+	let dot = String::from_utf8(topology.to_dot()).unwrap();
+	assert!(dot.starts_with("digraph mesh {\n"));
+	assert!(dot.contains("\t\"aa\" [label=\"say \\\"hi\\\" <&>\", color=\"green\"];\n"));
+	assert!(dot.contains("\t\"cc\" [label=\"cc\", color=\"red\"];\n"));
+	assert!(dot.contains("\t\"aa\" -> \"bb\" [label=\"1.00 / 0.50\", type=\"wifi\", dir=\"both\"];\n"));
+	assert!(!dot.contains("\"cc\" ->"));
+
+	let graphml = String::from_utf8(topology.to_graphml()).unwrap();
+	assert!(graphml.contains("<data key=\"hostname\">say &quot;hi&quot; &lt;&amp;&gt;</data>"));
+	assert!(!graphml.contains("<&>"));
+	assert_eq!(graphml.matches("<node ").count(), 3);
+	assert_eq!(graphml.matches("<edge ").count(), 1);
+	assert!(graphml.contains("\t\t<edge source=\"aa\" target=\"bb\">\n"));
+	assert!(graphml.ends_with("</graph>\n</graphml>\n"));
+}
@@ -1,57 +1,695 @@
 #[allow(unused_imports)]
-use crate::collector::{self, Collector, ResponseBuffer};
+use crate::collector::{self, CollectorHandle, Snapshot, SubscriberStats};
+use crate::api;
+use crate::auth::{Access, Auth};
+use crate::config::{self, FileFormat, Scope};
+use crate::encoding::Encoding;
 use crate::CONFIG;
 use crate::Endpoint;
-use crate::NodeResponse;
-use std;
-use std::sync::{Arc, Mutex};
-use tiny_http::{Server, Response, Request, Header};
+use crate::inventory;
+use crate::map::{self, BoundingBox};
+use crate::metrics;
+use crate::privacy::Redactor;
+use crate::query::{parse_params, percent_decode, Query};
+use crate::rollout::Report;
+use crate::sse::{self, Hub};
+use crate::websocket;
+use crate::Timestamp;
+use chrono::DateTime;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
 use serde_json as json;
+use std::fs;
+use std::io::{self, Cursor};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Server, Response, Request, Header, Method, SslConfig};
+
+/// how often a tls listener checks for a certificate reload
+const RELOAD_CHECK: Duration = Duration::from_secs(1);
+
+
+
+/// renders the located nodes of a snapshot
+type ExportFn = fn(&[Arc<crate::NodeResponse>], &crate::topology::Topology, Option<&BoundingBox>) -> Vec<u8>;
+
+
+/// which data a request may see
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum View {
+	/// personal data is removed as configured in `redact`
+	Public,
+	/// everything
+	Full,
+}
+
+impl View {
+	fn name(self) -> &'static str {
+		match self {
+			Self::Public => "public",
+			Self::Full => "full",
+		}
+	}
 
+	/// what clients without credentials may do on a listener with this view
+	fn scope(self) -> Scope {
+		match self {
+			Self::Public => Scope::Public,
+			Self::Full => Scope::Full,
+		}
+	}
+}
 
 
 pub struct Web {
-	collector: Arc<Mutex<Collector>>,
-	server: Server,
+	ctx: Context,
+	servers: Vec<(Server, View, Option<Tls>)>,
+}
+
+
+/// certificate of a https listener, replaced on SIGHUP
+struct Tls {
+	config: config::Tls,
+	reload: Arc<AtomicBool>,
+	/// stays open across reloads, so the port is never given up
+	listener: TcpListener,
+	/// certificate of the running server
+	running: SslConfig,
+}
+
+impl Tls {
+	fn bind(config: config::Tls, reload: Arc<AtomicBool>, addr: SocketAddr) -> Result<(Self, Server), String> {
+		let listener = TcpListener::bind(addr).map_err(|e| e.to_string())?;
+		let running = load(&config).map_err(|e| e.to_string())?;
+		let tls = Self { config, reload, listener, running };
+		let server = tls.serve(tls.running.clone())?;
+		Ok((tls, server))
+	}
+
+	fn serve(&self, ssl: SslConfig) -> Result<Server, String> {
+		let listener = self.listener.try_clone().map_err(|e| e.to_string())?;
+		Server::from_listener(listener, Some(ssl)).map_err(|e| e.to_string())
+	}
+
+	/// Serve the new certificate on the listener of `server`.
+	///
+	/// tiny_http can't swap the certificate of a running server, so the
+	/// server is replaced. Connections that are already open keep the old one.
+	/// A certificate that can't be loaded leaves the running server untouched.
+	fn rebind(&mut self, server: Server) -> Server {
+		let ssl = match load(&self.config).map_err(|e| e.to_string()).and_then(|ssl| validate(&ssl).map(|_| ssl)) {
+			Ok(ssl) => ssl,
+			Err(e) => {
+				error!("cannot reload certificate {}: {}. keeping the old one", self.config.certificate.display(), e);
+				return server;
+			}
+		};
+
+		// the accept thread of the old server has to stop before the new one
+		// starts, else both take turns accepting connections
+		drop(server);
+		match self.serve(ssl.clone()) {
+			Ok(server) => {
+				info!("reloaded certificate {}", self.config.certificate.display());
+				self.running = ssl;
+				server
+			}
+			Err(e) => {
+				error!("cannot serve certificate {}: {}. keeping the old one", self.config.certificate.display(), e);
+				self.restore()
+			}
+		}
+	}
+
+	/// restart the server with the certificate that worked before
+	fn restore(&self) -> Server {
+		loop {
+			match self.serve(self.running.clone()) {
+				Ok(server) => return server,
+				Err(e) => error!("cannot serve https on {:?}: {}", self.listener.local_addr(), e),
+			}
+			thread::sleep(RELOAD_CHECK);
+		}
+	}
+}
+
+fn load(config: &config::Tls) -> io::Result<SslConfig> {
+	Ok(SslConfig {
+		certificate: fs::read(&config.certificate)?,
+		private_key: fs::read(&config.key)?,
+	})
+}
+
+/// tiny_http only checks a certificate when it starts a server,
+/// so one is started on an unused port
+fn validate(ssl: &SslConfig) -> Result<(), String> {
+	let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).map_err(|e| e.to_string())?;
+	Server::from_listener(listener, Some(ssl.clone())).map(drop).map_err(|e| e.to_string())
+}
+
+
+/// shared between all listeners
+#[derive(Clone)]
+struct Context {
+	collector: CollectorHandle,
+	redactor: Arc<Redactor>,
+	auth: Arc<Auth>,
+	started: Timestamp,
+	target_release: Option<String>,
+	map: config::MapConfig,
+	hub: Hub,
+	/// redacted copy of the latest snapshot
+	public: Arc<Mutex<Option<Arc<Snapshot>>>>,
+}
+
+impl Context {
+	fn snapshot(&self, view: View) -> Arc<Snapshot> {
+		let snapshot = self.collector.snapshot();
+		if view == View::Full || self.redactor.is_noop() {
+			return snapshot;
+		}
+
+		let mut public = self.public.lock().unwrap();
+		match &*public {
+			Some(p) if p.generation() == snapshot.generation() => p.clone(),
+			_ => {
+				let p = Arc::new(snapshot.redacted(&self.redactor));
+				*public = Some(p.clone());
+				p
+			}
+		}
+	}
+
+	fn redactor(&self, view: View) -> Arc<Redactor> {
+		match view {
+			View::Public => self.redactor.clone(),
+			View::Full => Arc::new(Redactor::default()),
+		}
+	}
+
+	/// state of the daemon for the dashboard
+	fn handle_status(&self, req: Request, snapshot: &Snapshot, scope: Scope) {
+		// the names of the endpoints are internal
+		let subscribers = (scope >= Scope::Full).then(|| self.collector.subscriber_stats());
+		let status = status(snapshot, self.started, scope, CONFIG.requestd.offline_after, subscribers);
+
+		let res = Response::from_data(json::to_vec(&status).unwrap()).with_header(api::json_header());
+		req.respond(res).ok();
+	}
+
+	/// located nodes for gis tools, optionally within `?bbox=minlon,minlat,maxlon,maxlat`
+	fn handle_export(&self, req: Request, snapshot: &Snapshot, view: View, kind: &'static str, render: ExportFn) {
+		let content_type = match kind {
+			"nodes.kml" => "application/vnd.google-earth.kml+xml",
+			_ => "application/geo+json",
+		};
+
+		let query = req.url().split_once('?').map(|(_, q)| q.to_string()).unwrap_or_default();
+		let bbox = match parse_params(&query).into_iter().find(|(k, _)| k == "bbox") {
+			Some((_, bbox)) => match BoundingBox::parse(&bbox) {
+				Ok(b) => b,
+				Err(e) => return api::respond_error(req, 400, &e),
+			},
+			None => return handle_rendered(req, snapshot, view, kind, content_type, |s| render(s.responses(), s.topology(), None)),
+		};
+
+		// every box is different, so it's not cached
+		let body = render(snapshot.responses(), snapshot.topology(), Some(&bbox));
+		let mut res = encoded_response(negotiate(&req), &body);
+		res.add_header(Header::from_bytes("Content-Type", content_type).unwrap());
+		if let Some(private) = private_header(view) {
+			res.add_header(private);
+		}
+		req.respond(res).ok();
+	}
+
+	fn respond_unauthorized(&self, req: Request, message: &str) {
+		let challenge = Header::from_bytes("WWW-Authenticate", self.auth.challenge()).unwrap();
+		req.respond(api::error_response(401, message).with_header(challenge)).ok();
+	}
+
+	fn serve(&self, mut server: Server, view: View, mut tls: Option<Tls>) -> ! {
+		loop {
+			if let Some(tls) = &mut tls {
+				if tls.reload.swap(false, Ordering::Relaxed) {
+					server = tls.rebind(server);
+				}
+			}
+
+			let req = match &tls {
+				// wake up regularly to check for a reload
+				Some(_) => server.recv_timeout(RELOAD_CHECK),
+				None => server.recv().map(Some),
+			};
+			match req {
+				Ok(Some(req)) => self.handle(req, view),
+				Ok(None) => (),
+				Err(e) => warn!("http server error: {}", e),
+			}
+		}
+	}
+
+	fn handle(&self, req: Request, listener: View) {
+		let scope = match self.auth.authenticate(header(&req, "Authorization")) {
+			Access::Granted(scope) => scope.max(listener.scope()),
+			Access::Anonymous if listener == View::Public && self.auth.is_required() => {
+				return self.respond_unauthorized(req, "authentication required");
+			}
+			Access::Anonymous => listener.scope(),
+			Access::Invalid => return self.respond_unauthorized(req, "invalid credentials"),
+		};
+		let view = if scope >= Scope::Full { View::Full } else { View::Public };
+
+		let path = req.url().split('?').next().unwrap_or_default().to_string();
+
+		// the only action, everything else is read only
+		if let Some(nodeid) = path.strip_prefix("/nodes/").and_then(|p| p.strip_suffix("/refresh")) {
+			match req.method() {
+				Method::Post if scope >= Scope::Admin => {
+					api::handle_refresh(req, &self.collector, &self.redactor(view), &percent_decode(nodeid))
+				}
+				Method::Post => api::respond_error(req, 403, "admin scope required"),
+				_ => api::respond_not_allowed(req, "POST"),
+			}
+			return;
+		}
+		// checks the method itself
+		if let Some(path) = path.strip_prefix("/nodes/") {
+			return api::handle_node(req, &self.snapshot(view), path, view != View::Public);
+		}
+		if !matches!(req.method(), Method::Get | Method::Head) {
+			return api::respond_not_allowed(req, "GET, HEAD");
+		}
+
+		match path.as_str() {
+			"/" | "/index.html" => handle_index(req),
+			"/map" => handle_page(req, include_str!("map.html")),
+			"/export/nodes.geojson" => self.handle_export(req, &self.snapshot(view), view, "nodes.geojson", map::to_geojson),
+			"/export/nodes.kml" => self.handle_export(req, &self.snapshot(view), view, "nodes.kml", map::to_kml),
+			"/map.json" => handle_rendered(req, &self.snapshot(view), view, "map.json", api::JSON, |s| {
+				map::render(s.responses(), s.topology(), &self.map)
+			}),
+			"/responses" => handle_responses(req, &self.snapshot(view), view),
+			"/status" => self.handle_status(req, &self.snapshot(view), scope),
+			"/metrics" => {
+				let subscribers = (scope >= Scope::Full).then(|| self.collector.subscriber_stats());
+				handle_metrics(req, &self.snapshot(view), view, subscribers)
+			}
+			"/meshviewer.json" => handle_format(req, &self.snapshot(view), view, FileFormat::Meshviewer),
+			"/nodes.json" => handle_format(req, &self.snapshot(view), view, FileFormat::NodesV2),
+			"/nodes.v1.json" => handle_format(req, &self.snapshot(view), view, FileFormat::NodesV1),
+			"/graph.json" => handle_format(req, &self.snapshot(view), view, FileFormat::Graph),
+			"/topology.json" => handle_rendered(req, &self.snapshot(view), view, "topology.json", "application/json", |s| {
+				s.topology().to_json()
+			}),
+			"/topology.dot" => handle_rendered(req, &self.snapshot(view), view, "topology.dot", "text/vnd.graphviz", |s| {
+				s.topology().to_dot()
+			}),
+			"/topology.graphml" => {
+				handle_rendered(req, &self.snapshot(view), view, "topology.graphml", "application/graphml+xml", |s| {
+					s.topology().to_graphml()
+				})
+			}
+			"/reboots" => handle_rendered(req, &self.snapshot(view), view, "reboots.json", "application/json", |s| {
+				json::to_vec(s.reboots()).unwrap()
+			}),
+			"/alerts" => handle_rendered(req, &self.snapshot(view), view, "alerts.json", "application/json", |s| {
+				json::to_vec(s.alerts()).unwrap()
+			}),
+			"/inventory" => handle_rendered(req, &self.snapshot(view), view, "inventory.json", "application/json", |s| {
+				json::to_vec(s.inventory()).unwrap()
+			}),
+			"/inventory.html" => handle_rendered(req, &self.snapshot(view), view, "inventory.html", "text/html", |s| {
+				inventory::to_html(s.inventory())
+			}),
+			"/rollout.json" => handle_rendered(req, &self.snapshot(view), view, "rollout.json", "application/json", |s| {
+				Report::new(s, self.target_release.as_deref()).to_json()
+			}),
+			"/rollout.html" => handle_rendered(req, &self.snapshot(view), view, "rollout.html", "text/html", |s| {
+				Report::new(s, self.target_release.as_deref()).to_html()
+			}),
+			"/events" => sse::handle(req, &self.hub, self.redactor(view)),
+			"/nodes" => handle_rendered(req, &self.snapshot(view), view, "nodes", api::JSON, api::render_nodes),
+			p => api::respond_error(req, 404, &format!("{} not found", p)),
+		}
+	}
 }
 
 
 fn handle_index(req: Request) {
-	let mut res = Response::from_string(include_str!("index.html"));
-	res.add_header(Header::from_bytes("Content-Type", "text/html").unwrap());
+	handle_page(req, include_str!("index.html"));
+}
+
+fn handle_page(req: Request, html: &str) {
+	let mut res = Response::from_string(html);
+	res.add_header(Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap());
+
+	req.respond(res).ok();
+}
+
+
+fn handle_responses(req: Request, snapshot: &Snapshot, view: View) {
+	let query = req.url().split_once('?').map(|(_, q)| q.to_string()).unwrap_or_default();
+
+	match query.as_str() {
+		"" => respond_cached(req, snapshot, view, "responses", "application/json", || Arc::new(snapshot.json().to_vec())),
+		query => handle_query(req, snapshot, view, query),
+	}
+}
 
-	req.respond(res).unwrap();
+/// filtered responses. Not cached, as every query is different
+fn handle_query(req: Request, snapshot: &Snapshot, view: View, query: &str) {
+	let query = match Query::parse(query) {
+		Ok(q) => q,
+		Err(e) => return api::respond_error(req, 400, &e),
+	};
+	let page = query.apply(snapshot.responses(), chrono::Utc::now());
+
+	let encoding = negotiate(&req);
+	let mut res = encoded_response(encoding, &json::to_vec(&page.items).unwrap());
+	res.add_header(api::json_header());
+	res.add_header(Header::from_bytes("X-Total-Count", page.total.to_string()).unwrap());
+	if let Some(cursor) = page.next_cursor {
+		res.add_header(Header::from_bytes("X-Next-Cursor", cursor).unwrap());
+	}
+	if let Some(private) = private_header(view) {
+		res.add_header(private);
+	}
+
+	req.respond(res).ok();
 }
 
+/// `subscribers` are left out if `None`, like on `/status`
+fn handle_metrics(req: Request, snapshot: &Snapshot, view: View, subscribers: Option<Vec<SubscriberStats>>) {
+	let mut body = metrics::render(snapshot.responses());
+	body.push_str(&metrics::render_reboots(snapshot.reboots()));
+	body.push_str(&metrics::render_alerts(snapshot.alerts()));
+	body.push_str(&metrics::render_inventory(snapshot.inventory()));
+	if let Some(subscribers) = subscribers {
+		body.push_str(&metrics::render_subscribers(&subscribers));
+	}
 
-fn handle_responses(req: Request, all_nodes: Vec<NodeResponse>) {
-	let mut res = Response::from_data(json::to_vec(&all_nodes).unwrap());
-	res.add_header(Header::from_bytes("Content-Type", "application/json").unwrap());
+	let mut res = Response::from_string(body);
+	res.add_header(Header::from_bytes("Content-Type", metrics::CONTENT_TYPE).unwrap());
+	if let Some(private) = private_header(view) {
+		res.add_header(private);
+	}
 
-	req.respond(res).unwrap();
+	req.respond(res).ok();
+}
+
+/// `Cache-Control: private` for everything but the public view
+fn private_header(view: View) -> Option<Header> {
+	(view != View::Public).then(api::private_header)
+}
+
+/// serve something that is rendered only once per snapshot
+fn handle_rendered<F>(req: Request, snapshot: &Snapshot, view: View, kind: &'static str, content_type: &str, render: F)
+where
+	F: FnOnce(&Snapshot) -> Vec<u8>,
+{
+	respond_cached(req, snapshot, view, kind, content_type, || snapshot.rendered(kind, render));
+}
+
+/// Answer with a body that only changes with the snapshot.
+///
+/// Clients that already have this snapshot get a 304, everyone else
+/// the body compressed as they prefer.
+fn respond_cached<F>(req: Request, snapshot: &Snapshot, view: View, kind: &'static str, content_type: &str, body: F)
+where
+	F: FnOnce() -> Arc<Vec<u8>>,
+{
+	let etag = etag(snapshot, view);
+	let mut cache_headers = vec![
+		Header::from_bytes("ETag", etag.as_str()).unwrap(),
+		Header::from_bytes("Last-Modified", http_date(snapshot.created())).unwrap(),
+	];
+	cache_headers.extend(private_header(view));
+
+	if is_fresh(&req, &etag, snapshot.created()) {
+		let mut res = Response::empty(304);
+		for h in cache_headers {
+			res.add_header(h);
+		}
+		req.respond(res).ok();
+		return;
+	}
+
+	let encoding = negotiate(&req);
+	let mut res = match encoding {
+		Encoding::Identity => Response::from_data(body().to_vec()),
+		e => Response::from_data(snapshot.encoded(kind, e, body).to_vec())
+			.with_header(Header::from_bytes("Content-Encoding", e.as_str()).unwrap()),
+	};
+	res.add_header(Header::from_bytes("Vary", "Accept-Encoding, Authorization").unwrap());
+	res.add_header(Header::from_bytes("Content-Type", content_type).unwrap());
+	for h in cache_headers {
+		res.add_header(h);
+	}
+
+	req.respond(res).ok();
+}
+
+fn header<'a>(req: &'a Request, name: &'static str) -> Option<&'a str> {
+	req.headers().iter().find(|h| h.field.equiv(name)).map(|h| h.value.as_str())
+}
+
+fn negotiate(req: &Request) -> Encoding {
+	header(req, "Accept-Encoding").map_or(Encoding::Identity, Encoding::negotiate)
+}
+
+fn encoded_response(encoding: Encoding, body: &[u8]) -> Response<Cursor<Vec<u8>>> {
+	let mut res = Response::from_data(encoding.encode(body));
+	res.add_header(Header::from_bytes("Vary", "Accept-Encoding, Authorization").unwrap());
+	if encoding != Encoding::Identity {
+		res.add_header(Header::from_bytes("Content-Encoding", encoding.as_str()).unwrap());
+	}
+	res
+}
+
+/// changes with every snapshot, also across restarts. Redacted and
+/// unredacted bodies of the same snapshot get different tags.
+fn etag(snapshot: &Snapshot, view: View) -> String {
+	format!("W/\"{:x}-{}-{}\"", snapshot.created().timestamp_millis(), snapshot.generation(), view.name())
+}
+
+fn http_date(t: Timestamp) -> String {
+	t.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// the client's copy is still up to date
+fn is_fresh(req: &Request, etag: &str, modified: Timestamp) -> bool {
+	// weak comparison, the body is the same in all encodings
+	let strip = |t: &str| t.trim().trim_start_matches("W/").to_string();
+
+	if let Some(tags) = header(req, "If-None-Match") {
+		return tags.split(',').any(|t| t.trim() == "*" || strip(t) == strip(etag));
+	}
+
+	match header(req, "If-Modified-Since").and_then(|d| DateTime::parse_from_rfc2822(d).ok()) {
+		Some(since) => modified.timestamp() <= since.timestamp(),
+		None => false,
+	}
+}
+
+/// `/status`. `subscribers` are left out if `None`
+fn status(
+	snapshot: &Snapshot,
+	started: Timestamp,
+	scope: Scope,
+	offline_after: u64,
+	subscribers: Option<Vec<SubscriberStats>>,
+) -> json::Value {
+	let now = chrono::Utc::now();
+	let online = snapshot.responses().iter().filter(|r| r.responded_within(offline_after)).count();
+
+	let mut status = json::json!({
+		"version": env!("CARGO_PKG_VERSION"),
+		"started": started,
+		"uptime": (now - started).num_seconds(),
+		"scope": scope,
+		"offline_after": offline_after,
+		"snapshot": {
+			"generation": snapshot.generation(),
+			"created": snapshot.created(),
+		},
+		"nodes": {
+			"total": snapshot.responses().len(),
+			"online": online,
+		},
+		"alerts": snapshot.alerts().len(),
+	});
+	if let Some(subscribers) = subscribers {
+		status["subscribers"] = json::json!(subscribers);
+	}
+
+	status
+}
+
+fn handle_format(req: Request, snapshot: &Snapshot, view: View, format: FileFormat) {
+	handle_rendered(req, snapshot, view, format.name(), "application/json", |s| format.render(s));
 }
 
 impl Endpoint for Web {
-	fn new(c: Arc<Mutex<Collector>>) -> Self {
-		let server = Server::http(CONFIG.web.clone().unwrap().listen).unwrap();
+	fn new(c: CollectorHandle) -> Self {
+		let conf = CONFIG.web.clone().unwrap();
+		let auth = Arc::new(Auth::new(&conf));
+
+		// credentials must not cross the network in cleartext next to a https listener
+		if conf.websocket_listen.is_some() && conf.tls.is_some() {
+			error!("websocket_listen is plain http and can't be used with tls. put a tls proxy in front of it instead");
+			process::exit(1);
+		}
+
+		let public = match conf.tls {
+			Some(config) => {
+				let reload = Arc::new(AtomicBool::new(false));
+				signal_hook::flag::register(signal_hook::consts::SIGHUP, reload.clone()).unwrap();
+
+				let listen = conf.listen;
+				let (tls, server) = Tls::bind(config, reload, listen).unwrap_or_else(|e| {
+					error!("cannot serve https on {}: {}", listen, e);
+					process::exit(1);
+				});
+				info!("serving https on {}", listen);
+				(server, View::Public, Some(tls))
+			}
+			None => (Server::http(conf.listen).unwrap(), View::Public, None),
+		};
+		let mut servers = vec![public];
+
+		if let Some(internal) = conf.internal_listen {
+			info!("serving unredacted data on {}", internal);
+			servers.push((Server::http(internal).unwrap(), View::Full, None));
+		}
+
+		let queue = CONFIG.requestd.event_queue.clone();
+		let hub = Hub::start(conf.event_replay, c.get_events_receiver("web", queue));
+		let redactor = Arc::new(Redactor::new(conf.redact));
+
+		if let Some(addr) = conf.websocket_listen {
+			let collector = c.clone();
+			let refresh = Arc::new(move |redactor: &Redactor, nodeid: &str| api::refresh(&collector, redactor, nodeid));
+			let listener = websocket::Listener::new(hub.clone(), refresh, redactor.clone(), auth.clone());
+			if let Err(e) = listener.start(addr) {
+				error!("cannot serve websockets on {}: {}", addr, e);
+				process::exit(1);
+			}
+		}
 
 		Self {
-			collector: c,
-			server: server,
+			ctx: Context {
+				collector: c,
+				hub,
+				redactor,
+				auth,
+				started: chrono::Utc::now(),
+				target_release: conf.target_release,
+				map: conf.map,
+				public: Arc::new(Mutex::new(None)),
+			},
+			servers,
 		}
 	}
 
 	fn start(self) -> ! {
-		for req in self.server.incoming_requests() {
-			match req.url() {
-				"/responses" => {
-					let responses = self.collector.lock().unwrap().all_responses();
-					handle_responses(req, responses);
-				}
-				_ => handle_index(req),
-			}
-		};
+		let mut servers = self.servers;
+		let (server, view, tls) = servers.remove(0);
+
+		for (server, view, tls) in servers {
+			let ctx = self.ctx.clone();
+			thread::spawn(move || ctx.serve(server, view, tls));
+		}
 
-		panic!("http endpoint loop returned. (this should not happen")
+		self.ctx.serve(server, view, tls)
 	}
 }
+
+
+
+#[test]
+fn status_shape() {
+	let now = chrono::Utc::now();
+	let response = |nodeid: &str, age: i64| Arc::new(crate::NodeResponse {
+		nodeid: nodeid.to_string(),
+		remote: "fe80::1".parse().unwrap(),
+		timestamp: now - chrono::Duration::seconds(age),
+		data: json::json!({}),
+	});
+	let snapshot = Snapshot::new(
+		7,
+		now,
+		vec![response("a", 10), response("b", 500)],
+		180,
+		Default::default(),
+		Default::default(),
+		vec![],
+		vec![],
+	);
+
+	let public = status(&snapshot, now, Scope::Public, 180, None);
+	assert_eq!(public["offline_after"], 180);
+	assert_eq!(public["nodes"], json::json!({"total": 2, "online": 1}));
+	assert_eq!(public["snapshot"]["generation"], 7);
+	assert_eq!(public["snapshot"]["created"], json::json!(now));
+	assert_eq!(public["scope"], "public");
+	assert!(public.get("subscribers").is_none());
+
+	let full = status(&snapshot, now, Scope::Full, 180, Some(vec![]));
+	assert_eq!(full["subscribers"], json::json!([]));
+}
+
+#[test]
+fn conditional_requests() {
+	let created = chrono::TimeZone::timestamp_opt(&chrono::Utc, 1_600_000_000, 0).unwrap();
+	let snapshot = |generation: u64| {
+		let created = created + chrono::Duration::seconds(generation as i64);
+		Snapshot::new(generation, created, vec![], 180, Default::default(), Default::default(), vec![], vec![])
+	};
+
+	let server = Server::http("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/responses", server.server_addr());
+	let call = |snapshot: &Snapshot, view: View, headers: &[(&'static str, &str)]| {
+		let headers: Vec<(&'static str, String)> = headers.iter().map(|(k, v)| (*k, v.to_string())).collect();
+		let url = url.clone();
+		let client = std::thread::spawn(move || {
+			let mut req = ureq::get(&url);
+			for (k, v) in &headers {
+				req = req.set(k, v);
+			}
+			let res = req.call().unwrap();
+			let header = |name: &str| res.header(name).unwrap_or_default().to_string();
+			(res.status(), header("ETag"), header("Last-Modified"), header("Cache-Control"))
+		});
+
+		let req = server.recv().unwrap();
+		respond_cached(req, snapshot, view, "responses", "application/json", || Arc::new(b"[]".to_vec()));
+		client.join().unwrap()
+	};
+
+	let first = snapshot(1);
+	let (status, etag, modified, cache_control) = call(&first, View::Public, &[]);
+	assert_eq!(status, 200);
+	assert_eq!(modified, "Sun, 13 Sep 2020 12:26:41 GMT");
+	assert_eq!(cache_control, "");
+
+	assert_eq!(call(&first, View::Public, &[("If-None-Match", &etag)]).0, 304);
+	assert_eq!(call(&first, View::Public, &[("If-Modified-Since", &modified)]).0, 304);
+
+	// a new snapshot has a new tag and is newer
+	let second = snapshot(2);
+	let (status, new_etag, _, _) = call(&second, View::Public, &[("If-None-Match", &etag)]);
+	assert_eq!(status, 200);
+	assert_ne!(new_etag, etag);
+	assert_eq!(call(&second, View::Public, &[("If-Modified-Since", &modified)]).0, 200);
+
+	// the unredacted view is a different body
+	let (status, full_etag, _, cache_control) = call(&second, View::Full, &[("If-None-Match", &new_etag)]);
+	assert_eq!(status, 200);
+	assert_ne!(full_etag, new_etag);
+	assert_eq!(cache_control, "private");
+}
@@ -0,0 +1,418 @@
+//! bidirectional event stream for dashboards
+//!
+//! The client sends json messages with an `op`:
+//!
+//! - `{"op": "subscribe", "id": "alerts", "type": ["alert"]}`: events matching the filter are sent
+//!   tagged with the subscription id. `nodeid`, `category` and `type` work like on `/events`.
+//! - `{"op": "unsubscribe", "id": "alerts"}`
+//! - `{"op": "refresh", "nodeid": "c04a00dd692a"}`: request the node right now. Needs the admin scope
+//!
+//! Every message is answered with an `ok` or an `error`.
+//!
+//! Served on its own listener: tiny_http can't split an upgraded connection,
+//! but reading the client and sending events needs both directions at once.
+//! The listener is plain http, so it refuses to start next to a https one.
+use crate::auth::{Access, Auth};
+use crate::config::Scope;
+use crate::privacy::Redactor;
+use crate::sse::{Filter, Hub, Numbered};
+use crossbeam::channel::{self, Receiver, Sender};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use serde::Deserialize;
+use serde_json as json;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+const PATH: &str = "/ws";
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// client messages waiting to be answered
+const CLIENT_QUEUE: usize = 16;
+
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientMessage {
+	Subscribe {
+		id: String,
+		#[serde(flatten)]
+		filter: Filter,
+	},
+	Unsubscribe {
+		id: String,
+	},
+	Refresh {
+		nodeid: String,
+	},
+}
+
+
+/// Requests a node right now. The node id is the one the client knows,
+/// so it may be redacted by the redactor. False for unknown nodes.
+pub type RefreshFn = dyn Fn(&Redactor, &str) -> bool + Send + Sync;
+
+
+/// state of one connection
+struct Session {
+	refresh: Arc<RefreshFn>,
+	redactor: Arc<Redactor>,
+	/// may request nodes
+	admin: bool,
+	subscriptions: BTreeMap<String, Filter>,
+}
+
+impl Session {
+	/// the answer to a message of the client
+	fn handle(&mut self, text: &str) -> json::Value {
+		let msg: ClientMessage = match json::from_str(text) {
+			Ok(m) => m,
+			Err(e) => return json::json!({"op": "error", "error": e.to_string()}),
+		};
+
+		match msg {
+			ClientMessage::Subscribe { id, filter } => {
+				self.subscriptions.insert(id.clone(), filter);
+				json::json!({"op": "ok", "id": id})
+			}
+			ClientMessage::Unsubscribe { id } => match self.subscriptions.remove(&id) {
+				Some(_) => json::json!({"op": "ok", "id": id}),
+				None => json::json!({"op": "error", "error": format!("no subscription {}", id)}),
+			},
+			ClientMessage::Refresh { .. } if !self.admin => {
+				json::json!({"op": "error", "error": "refresh needs the admin scope"})
+			}
+			ClientMessage::Refresh { nodeid } => match (self.refresh)(&self.redactor, &nodeid) {
+				true => json::json!({"op": "ok", "nodeid": nodeid}),
+				false => json::json!({"op": "error", "error": format!("unknown node {}", nodeid)}),
+			},
+		}
+	}
+
+	/// answers the client and sends the events until either side is gone
+	fn run(mut self, mut ws: WebSocket<Shared>, messages: Receiver<String>, events: Receiver<Numbered>) {
+		loop {
+			let ok = channel::select! {
+				recv(messages) -> text => match text {
+					Ok(text) => {
+						let answer = self.handle(&text);
+						ws.write_message(Message::Text(answer.to_string())).is_ok()
+					}
+					// the client closed the connection
+					Err(_) => false,
+				},
+				recv(events) -> event => match event {
+					Ok(numbered) => self.send_event(&mut ws, numbered),
+					Err(_) => false,
+				},
+				default(PING_INTERVAL) => ws.write_message(Message::Ping(vec![])).is_ok(),
+			};
+
+			if !ok {
+				return;
+			}
+		}
+	}
+
+	/// to every subscription the event matches
+	fn send_event(&self, ws: &mut WebSocket<Shared>, (id, event): Numbered) -> bool {
+		let mut event = (*event).clone();
+		self.redactor.apply_event(&mut event);
+
+		for (subscription, filter) in &self.subscriptions {
+			if let Some(e) = filter.apply(&event) {
+				let msg = json::json!({"op": "event", "id": subscription, "event_id": id, "type": e.kind(), "event": e});
+				if ws.write_message(Message::Text(msg.to_string())).is_err() {
+					return false;
+				}
+			}
+		}
+
+		true
+	}
+}
+
+
+/// The connection as seen by one thread.
+///
+/// Both threads read and write on their own handle. Every write is done
+/// completely under a shared lock, so frames of the two don't interleave.
+struct Shared {
+	read: TcpStream,
+	write: Arc<Mutex<TcpStream>>,
+}
+
+impl Shared {
+	fn new(stream: &TcpStream, write: &Arc<Mutex<TcpStream>>) -> io::Result<Self> {
+		Ok(Self {
+			read: stream.try_clone()?,
+			write: write.clone(),
+		})
+	}
+}
+
+impl Read for Shared {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.read.read(buf)
+	}
+}
+
+impl Write for Shared {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		// tungstenite writes whole frames
+		self.write.lock().unwrap().write_all(buf)?;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.write.lock().unwrap().flush()
+	}
+}
+
+
+/// accepts websocket connections on `addr`
+pub struct Listener {
+	hub: Hub,
+	refresh: Arc<RefreshFn>,
+	/// for clients without the full scope
+	redactor: Arc<Redactor>,
+	auth: Arc<Auth>,
+}
+
+impl Listener {
+	pub fn new(hub: Hub, refresh: Arc<RefreshFn>, redactor: Arc<Redactor>, auth: Arc<Auth>) -> Self {
+		Self {
+			hub,
+			refresh,
+			redactor,
+			auth,
+		}
+	}
+
+	/// returns the address actually bound, in case the port was 0
+	pub fn start(self, addr: SocketAddr) -> io::Result<SocketAddr> {
+		let listener = TcpListener::bind(addr)?;
+		let addr = listener.local_addr()?;
+		info!("serving websockets on {}", addr);
+
+		let this = Arc::new(self);
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					Ok(stream) => {
+						let this = this.clone();
+						thread::spawn(move || this.handle(stream));
+					}
+					Err(e) => warn!("cannot accept websocket connection: {}", e),
+				}
+			}
+		});
+
+		Ok(addr)
+	}
+
+	/// the scope of the client or why it is rejected
+	fn authorize(&self, req: &Request) -> Result<Scope, (StatusCode, &'static str)> {
+		if req.uri().path() != PATH {
+			return Err((StatusCode::NOT_FOUND, "not found"));
+		}
+
+		let authorization = req.headers().get("Authorization").and_then(|h| h.to_str().ok());
+		match self.auth.authenticate(authorization) {
+			Access::Granted(scope) => Ok(scope),
+			Access::Anonymous if self.auth.is_required() => Err((StatusCode::UNAUTHORIZED, "authentication required")),
+			Access::Anonymous => Ok(Scope::Public),
+			Access::Invalid => Err((StatusCode::UNAUTHORIZED, "invalid credentials")),
+		}
+	}
+
+	fn error_response(&self, (status, message): (StatusCode, &str)) -> ErrorResponse {
+		let mut res = tungstenite::http::Response::builder().status(status);
+		if status == StatusCode::UNAUTHORIZED {
+			res = res.header("WWW-Authenticate", self.auth.challenge());
+		}
+		res.body(Some(json::json!({ "error": message }).to_string())).unwrap()
+	}
+
+	fn handle(&self, stream: TcpStream) {
+		let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+		let write = match stream.try_clone() {
+			Ok(s) => Arc::new(Mutex::new(s)),
+			Err(e) => return warn!("cannot use websocket connection of {}: {}", peer, e),
+		};
+
+		let mut scope = Scope::Public;
+		let reader = Shared::new(&stream, &write).map_err(|e| e.to_string()).and_then(|shared| {
+			let handshake = Handshake {
+				listener: self,
+				scope: &mut scope,
+			};
+			tungstenite::accept_hdr(shared, handshake).map_err(|e| e.to_string())
+		});
+		let reader = match reader {
+			Ok(ws) => ws,
+			Err(e) => return debug!("websocket handshake with {} failed: {}", peer, e),
+		};
+		let writer = match Shared::new(&stream, &write) {
+			Ok(shared) => WebSocket::from_raw_socket(shared, Role::Server, None),
+			Err(e) => return warn!("cannot use websocket connection of {}: {}", peer, e),
+		};
+
+		let (messages_tx, messages) = channel::bounded(CLIENT_QUEUE);
+		thread::spawn(move || read_messages(reader, messages_tx));
+
+		let (_, events) = self.hub.subscribe(None);
+		let session = Session {
+			refresh: self.refresh.clone(),
+			redactor: if scope >= Scope::Full { Arc::new(Redactor::default()) } else { self.redactor.clone() },
+			admin: scope >= Scope::Admin,
+			subscriptions: BTreeMap::new(),
+		};
+		session.run(writer, messages, events);
+
+		// also ends the reader
+		stream.shutdown(Shutdown::Both).ok();
+		debug!("websocket client {} disconnected", peer);
+	}
+}
+
+
+/// checks the request of the websocket handshake
+struct Handshake<'a> {
+	listener: &'a Listener,
+	/// of the client, once accepted
+	scope: &'a mut Scope,
+}
+
+impl Callback for Handshake<'_> {
+	fn on_request(self, req: &Request, res: Response) -> Result<Response, ErrorResponse> {
+		*self.scope = self.listener.authorize(req).map_err(|e| self.listener.error_response(e))?;
+		Ok(res)
+	}
+}
+
+
+/// Passes the messages of the client to the session until the connection is closed.
+/// Pings and closing are handled by tungstenite.
+fn read_messages(mut ws: WebSocket<Shared>, messages: Sender<String>) {
+	loop {
+		match ws.read_message() {
+			Ok(Message::Text(text)) => {
+				if messages.send(text).is_err() {
+					return;
+				}
+			}
+			Ok(_) => (),
+			Err(_) => return,
+		}
+	}
+}
+
+
+
+
+
+#[test]
+fn subscription_protocol() {
+	let msg: ClientMessage = json::from_str(r#"{"op": "subscribe", "id": "a", "nodeid": ["c04a00dd692a"], "type": ["alert"]}"#).unwrap();
+	match msg {
+		ClientMessage::Subscribe { id, filter } => {
+			assert_eq!(id, "a");
+			assert_eq!(format!("{:?}", filter), r#"Filter { nodeids: ["c04a00dd692a"], categories: [], kinds: ["alert"] }"#);
+		}
+		m => panic!("unexpected {:?}", m),
+	}
+
+	assert!(json::from_str::<ClientMessage>(r#"{"op": "refresh"}"#).is_err());
+	assert!(json::from_str::<ClientMessage>(r#"{"op": "unsubscribe", "id": "a"}"#).is_ok());
+}
+
+#[test]
+fn subscribe_unsubscribe_and_refresh_over_a_socket() {
+	use crate::config::{AccessToken, WebEndpoint};
+
+	let hub = Hub::new(0);
+	let refreshed = Arc::new(Mutex::new(vec![]));
+	let r = refreshed.clone();
+	let refresh: Arc<RefreshFn> = Arc::new(move |_: &Redactor, nodeid: &str| {
+		r.lock().unwrap().push(nodeid.to_string());
+		nodeid == "c04a00dd692a"
+	});
+	let auth = Auth::new(&WebEndpoint {
+		tokens: vec![AccessToken {
+			token: "s3cr3t".to_string(),
+			scope: Scope::Admin,
+		}],
+		..Default::default()
+	});
+	let listener = Listener::new(hub.clone(), refresh, Arc::new(Redactor::default()), Arc::new(auth));
+	let addr = listener.start("127.0.0.1:0".parse().unwrap()).unwrap();
+
+	let connect = |token: Option<&str>| {
+		let mut req = tungstenite::http::Request::get(format!("ws://{}{}", addr, PATH));
+		if let Some(token) = token {
+			req = req.header("Authorization", format!("Bearer {}", token));
+		}
+		let stream = TcpStream::connect(addr).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		tungstenite::client(req.body(()).unwrap(), stream).map(|(ws, _)| ws).map_err(Box::new)
+	};
+	let read = |ws: &mut WebSocket<TcpStream>| loop {
+		if let Message::Text(text) = ws.read_message().unwrap() {
+			break json::from_str::<json::Value>(&text).unwrap();
+		}
+	};
+	let call = |ws: &mut WebSocket<TcpStream>, msg: json::Value| {
+		ws.write_message(Message::Text(msg.to_string())).unwrap();
+		read(ws)
+	};
+	let publish = |nodeid: &str| {
+		hub.publish(crate::events::Event::Response(crate::NodeResponse {
+			nodeid: nodeid.to_string(),
+			remote: "fe80::1".parse().unwrap(),
+			timestamp: chrono::Utc::now(),
+			data: json::json!({}),
+		}))
+	};
+
+	let mut ws = connect(Some("s3cr3t")).unwrap();
+	let answer = call(&mut ws, json::json!({"op": "subscribe", "id": "a", "nodeid": ["c04a00dd692a"]}));
+	assert_eq!(answer, json::json!({"op": "ok", "id": "a"}));
+
+	publish("000000000000");
+	publish("c04a00dd692a");
+	let event = read(&mut ws);
+	assert_eq!(event["op"], "event");
+	assert_eq!(event["id"], "a");
+	assert_eq!(event["type"], "response");
+	assert_eq!(event["event"]["nodeid"], "c04a00dd692a");
+
+	assert_eq!(call(&mut ws, json::json!({"op": "unsubscribe", "id": "a"}))["op"], "ok");
+	assert_eq!(call(&mut ws, json::json!({"op": "unsubscribe", "id": "a"}))["op"], "error");
+	publish("c04a00dd692a");
+
+	// no event in between, as nothing is subscribed anymore
+	let answer = call(&mut ws, json::json!({"op": "refresh", "nodeid": "c04a00dd692a"}));
+	assert_eq!(answer, json::json!({"op": "ok", "nodeid": "c04a00dd692a"}));
+	assert_eq!(call(&mut ws, json::json!({"op": "refresh", "nodeid": "000000000000"}))["op"], "error");
+	assert_eq!(*refreshed.lock().unwrap(), vec!["c04a00dd692a", "000000000000"]);
+	ws.close(None).unwrap();
+
+	// anonymous clients may only subscribe
+	let mut ws = connect(None).unwrap();
+	let answer = call(&mut ws, json::json!({"op": "refresh", "nodeid": "c04a00dd692a"}));
+	assert_eq!(answer["error"], "refresh needs the admin scope");
+	assert_eq!(refreshed.lock().unwrap().len(), 2);
+
+	match connect(Some("wrong")).map(|_| ()).map_err(|e| *e) {
+		Err(tungstenite::HandshakeError::Failure(tungstenite::Error::Http(res))) => assert_eq!(res.status(), StatusCode::UNAUTHORIZED),
+		r => panic!("unexpected {:?}", r),
+	}
+}
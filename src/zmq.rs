@@ -1,37 +1,41 @@
-use crate::Collector;
 use crate::Endpoint;
-use crate::NodeResponse;
 use crate::CONFIG;
-use crossbeam::channel as crossbeam;
+use crate::collector::{CollectorHandle, EventReceiver};
+use crate::privacy::Redactor;
 use log::{trace};
 use zmq::{self, SocketType};
 use serde_json as json;
-use std::sync::{Arc, Mutex};
 
 const ZMQ_TOPIC: &str = "requestd";
 
 pub struct Zmq {
 	zsocket: zmq::Socket,
-	events_receiver: crossbeam::Receiver<NodeResponse>,
+	events_receiver: EventReceiver,
+	redactor: Redactor,
 }
 
 // impl Zmq {
 // }
 
 impl Endpoint for Zmq {
-	fn new(c: Arc<Mutex<Collector>>) -> Self {
+	fn new(c: CollectorHandle) -> Self {
 		let ctx = zmq::Context::new();
 		let zsocket = ctx.socket(SocketType::PUB).unwrap();
-		zsocket.bind(&CONFIG.zmq.clone().unwrap().bind_to).unwrap();
+		let conf = CONFIG.zmq.clone().unwrap();
+		zsocket.bind(&conf.bind_to).unwrap();
+
+		let queue = conf.queue.unwrap_or_else(|| CONFIG.requestd.event_queue.clone());
 
 		Self {
 			zsocket: zsocket,
-			events_receiver: c.lock().unwrap().get_events_receiver(),
+			events_receiver: c.get_events_receiver("zmq", queue),
+			redactor: Redactor::new(conf.redact),
 		}
 	}
 
 	fn start(self) -> ! {
-		for event in &self.events_receiver {
+		for mut event in &self.events_receiver {
+			self.redactor.apply_event(&mut event);
 			trace!("sending zmq message");
 			self.zsocket.send(ZMQ_TOPIC, zmq::SNDMORE).unwrap();
 			self.zsocket.send(json::to_vec(&event).unwrap(), 0).unwrap();